@@ -1,7 +1,7 @@
 use crate::config;
 use std::fs::File;
 use std::io;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, IntoRawFd};
 
 pub fn load_args(cfg: &mut config::Config, argv: Vec<String>) {
     let help_msg = "
@@ -16,8 +16,9 @@ pub fn load_args(cfg: &mut config::Config, argv: Vec<String>) {
     ";
     
     for (i, _val) in  argv.iter().enumerate() {
-        if argv[i].ends_with(".sh") || argv[i].ends_with(".rsh") && cfg.stdin_to_execute == io::stdin().as_raw_fd() {
-            cfg.stdin_to_execute = File::open(&argv[i]).unwrap().as_raw_fd();
+        if (argv[i].ends_with(".sh") || argv[i].ends_with(".rsh")) && cfg.stdin_to_execute == io::stdin().as_raw_fd() {
+            cfg.stdin_to_execute = File::open(&argv[i]).unwrap().into_raw_fd();
+            cfg.variables.insert(String::from("0"), argv[i].clone());
             continue;
         }
         
@@ -1,6 +1,13 @@
 use std::env;
 use std::os::unix::io::AsRawFd;
 use crate::config::Config;
+use crate::completion::{self, CompletionSpec};
+use crate::keymap;
+use crate::editor;
+use crate::history;
+use crate::expand;
+use crate::parser;
+use std::path::PathBuf;
 use std::io;
 
 pub fn cd_builtin(argv: &Vec<&String>, _config: &mut Config) -> Result<(i32, i32, i32), String> {
@@ -17,7 +24,7 @@ pub fn cd_builtin(argv: &Vec<&String>, _config: &mut Config) -> Result<(i32, i32
 
 pub fn help_builtin(_argv: &Vec<&String>, _config: &mut Config) -> Result<(i32, i32, i32), String> {
     let stdout = io::stdout().as_raw_fd();
-    println!("Builtins:\n\nhelp - prints this help message\ncd - changes directory\nexit - exits the program with specified return code\n");
+    println!("Builtins:\n\nhelp - prints this help message\nbind - binds keys to line editor actions\ncd - changes directory\ncomplete - sets how arguments of a command are completed\nabbr - manages abbreviations expanded while typing\nexit - exits the program with specified return code\nfc - edits and runs commands from history\nset - sets shell options\n");
     Ok((1, 0, stdout))
 }
 
@@ -67,9 +74,467 @@ pub fn exit_builtin(argv: &Vec<&String>, _config: &mut Config) -> Result<(i32, i
     return Ok((0, status_code, stdout));
 }
 
+pub fn complete_builtin(argv: &Vec<&String>, config: &mut Config) -> Result<(i32, i32, i32), String> {
+    let stdout = io::stdout().as_raw_fd();
+    let help_msg = "Usage:\n\ncomplete [-cdf] [-W wordlist] [-F function] [name ...]\ncomplete -p [name ...]\ncomplete -r [name ...]\n";
+
+    let mut spec = CompletionSpec::default();
+    let mut print = false;
+    let mut remove = false;
+    let mut names: Vec<String> = Vec::new();
+
+    let mut args = argv.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-W" => match args.next() {
+                Some(words) => spec.words.extend(words.split_whitespace().map(|word| word.to_string())),
+                None => {
+                    println!("{}", help_msg);
+                    return Ok((1, 1, stdout));
+                }
+            },
+            "-F" => match args.next() {
+                Some(function) => spec.function = Some(function.to_string()),
+                None => {
+                    println!("{}", help_msg);
+                    return Ok((1, 1, stdout));
+                }
+            },
+            flags if flags.starts_with('-') && flags.len() > 1 => {
+                for flag in flags[1..].chars() {
+                    match flag {
+                        'c' => spec.commands = true,
+                        'd' => spec.directories = true,
+                        'f' => spec.files = true,
+                        'p' => print = true,
+                        'r' => remove = true,
+                        _ => {
+                            println!("{}", help_msg);
+                            return Ok((1, 1, stdout));
+                        }
+                    }
+                }
+            },
+            name => names.push(name.to_string())
+        }
+    }
+
+    if remove {
+        if names.is_empty() {
+            config.completions.clear();
+        }
+
+        for name in names.iter() {
+            config.completions.remove(name);
+        }
+
+        return Ok((1, 0, stdout));
+    }
+
+    if print || names.is_empty() {
+        let mut to_print: Vec<&String> = if names.is_empty() {
+            config.completions.keys().collect()
+        }
+
+        else {
+            names.iter().collect()
+        };
+
+        to_print.sort();
+
+        let mut status = 0;
+
+        for name in to_print {
+            match config.completions.get(name) {
+                Some(spec) => println!("{}", completion::format_spec(name, spec)),
+                None => {
+                    println!("complete: {}: no completion specification", name);
+                    status = 1;
+                }
+            }
+        }
+
+        return Ok((1, status, stdout));
+    }
+
+    for name in names {
+        config.completions.insert(name, spec.clone());
+    }
+
+    Ok((1, 0, stdout))
+}
+
+pub fn abbr_builtin(argv: &Vec<&String>, config: &mut Config) -> Result<(i32, i32, i32), String> {
+    let stdout = io::stdout().as_raw_fd();
+    let help_msg = "Usage:\n\nabbr [-s]\nabbr [-a] name expansion ...\nabbr -e name ...\nabbr -l\n";
+
+    let mut args: Vec<&String> = argv.to_vec();
+
+    let flag = match args.first() {
+        Some(arg) if arg.starts_with('-') => Some(args.remove(0).as_str()),
+        _ => None
+    };
+
+    match flag {
+        None | Some("-s") | Some("--show") if args.is_empty() => {
+            let mut abbreviations: Vec<(&String, &String)> = config.abbreviations.iter().collect();
+            abbreviations.sort();
+
+            for (name, expansion) in abbreviations {
+                println!("abbr {} {}", name, expand::single_quote(expansion));
+            }
+        },
+        Some("-l") | Some("--list") if args.is_empty() => {
+            let mut names: Vec<&String> = config.abbreviations.keys().collect();
+            names.sort();
+
+            for name in names {
+                println!("{}", name);
+            }
+        },
+        Some("-e") | Some("--erase") if !args.is_empty() => {
+            let mut status = 0;
+
+            for name in args {
+                if config.abbreviations.remove(name.as_str()).is_none() {
+                    println!("abbr: {}: no such abbreviation", name);
+                    status = 1;
+                }
+            }
+
+            return Ok((1, status, stdout));
+        },
+        None | Some("-a") | Some("--add") if args.len() >= 2 => {
+            let name = args[0];
+
+            if name.contains(char::is_whitespace) {
+                println!("abbr: {}: abbreviation can't contain whitespace", name);
+                return Ok((1, 1, stdout));
+            }
+
+            let expansion = args[1..].iter().map(|arg| arg.as_str()).collect::<Vec<&str>>().join(" ");
+            config.abbreviations.insert(name.to_string(), expansion);
+        },
+        _ => {
+            println!("{}", help_msg);
+            return Ok((1, 1, stdout));
+        }
+    }
+
+    Ok((1, 0, stdout))
+}
+
+pub fn set_builtin(argv: &Vec<&String>, config: &mut Config) -> Result<(i32, i32, i32), String> {
+    let stdout = io::stdout().as_raw_fd();
+    let help_msg = "Usage:\n\nset\nset -o [option ...]\nset +o [option ...]\n";
+
+    if argv.is_empty() {
+        let mut variables: Vec<(&String, &String)> = config.variables.iter().collect();
+        variables.sort();
+
+        for (name, value) in variables {
+            println!("{}={}", name, value);
+        }
+
+        return Ok((1, 0, stdout));
+    }
+
+    let enable = match argv[0].as_str() {
+        "-o" => true,
+        "+o" => false,
+        _ => {
+            println!("{}", help_msg);
+            return Ok((1, 1, stdout));
+        }
+    };
+
+    if argv.len() == 1 {
+        let mut options: Vec<(&String, &bool)> = config.options.iter().collect();
+        options.sort();
+
+        for (name, value) in options {
+            println!("{:<15}{}", name, if *value { "on" } else { "off" });
+        }
+
+        return Ok((1, 0, stdout));
+    }
+
+    let mut status = 0;
+
+    for name in argv[1..].iter() {
+        if !config.options.contains_key(name.as_str()) {
+            println!("set: {}: invalid option name", name);
+            status = 1;
+            continue;
+        }
+
+        config.options.insert(name.to_string(), enable);
+
+        // there's always exactly one editing mode
+        match name.as_str() {
+            "vi" => {
+                config.options.insert(String::from("emacs"), !enable);
+            },
+            "emacs" => {
+                config.options.insert(String::from("vi"), !enable);
+            },
+            _ => {}
+        }
+    }
+
+    Ok((1, status, stdout))
+}
+
+pub fn bind_builtin(argv: &Vec<&String>, config: &mut Config) -> Result<(i32, i32, i32), String> {
+    let stdout = io::stdout().as_raw_fd();
+    let help_msg = "Usage:\n\nbind '\"keyseq\": action'\nbind -l\nbind -p\nbind -r keyseq\nbind -f file\n";
+
+    if argv.is_empty() {
+        println!("{}", help_msg);
+        return Ok((1, 1, stdout));
+    }
+
+    match argv[0].as_str() {
+        "-l" => {
+            for action in keymap::ACTIONS {
+                println!("{}", action);
+            }
+        },
+        "-p" => {
+            let mut bindings: Vec<(String, &String)> = config.keymap.iter()
+                .map(|(keys, action)| (keymap::format_key_sequence(keys), action))
+                .collect();
+
+            bindings.sort();
+
+            for (keys, action) in bindings {
+                println!("\"{}\": {}", keys, action);
+            }
+        },
+        "-r" if argv.len() == 2 => {
+            match keymap::parse_key_sequence(argv[1]) {
+                Ok(keys) => {
+                    config.keymap.remove(&keys);
+                },
+                Err(e) => {
+                    println!("bind: {}", e);
+                    return Ok((1, 1, stdout));
+                }
+            }
+        },
+        "-f" if argv.len() == 2 => {
+            if let Err(e) = keymap::load_inputrc_file(config, &PathBuf::from(argv[1])) {
+                println!("bind: {}", e);
+                return Ok((1, 1, stdout));
+            }
+        },
+        _ => {
+            let mut status = 0;
+
+            for binding in argv.iter() {
+                match keymap::parse_binding(binding) {
+                    Ok((keys, action)) => {
+                        config.keymap.insert(keys, action);
+                    },
+                    Err(e) => {
+                        println!("bind: {}", e);
+                        status = 1;
+                    }
+                }
+            }
+
+            return Ok((1, status, stdout));
+        }
+    }
+
+    Ok((1, 0, stdout))
+}
+
+// splits what came back from the editor into commands, a line only ends one when
+// nothing in the command is left open, like a quote or a trailing |
+fn split_commands(text: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current = String::new();
+
+    for line in text.lines() {
+        if current.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+
+        if !current.is_empty() {
+            current.push('\n');
+        }
+
+        current.push_str(line);
+
+        if !parser::is_incomplete(&current) {
+            commands.push(std::mem::take(&mut current));
+        }
+    }
+
+    if !current.is_empty() {
+        commands.push(current);
+    }
+
+    commands
+}
+
+pub fn fc_builtin(argv: &Vec<&String>, config: &mut Config) -> Result<(i32, i32, i32), String> {
+    let stdout = io::stdout().as_raw_fd();
+    let help_msg = "Usage:\n\nfc [-e editor] [first [last]]\nfc -l [-nr] [first [last]]\nfc -s [old=new] [first]\n";
+
+    let mut list = false;
+    let mut numbers = true;
+    let mut reverse = false;
+    let mut substitute = false;
+    let mut editor: Option<String> = None;
+    let mut operands: Vec<&String> = Vec::new();
+
+    let mut args = argv.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" => match args.next() {
+                Some(name) => editor = Some(name.to_string()),
+                None => {
+                    println!("{}", help_msg);
+                    return Ok((1, 1, stdout));
+                }
+            },
+            // -5 is an entry, not a flag
+            flags if flags.starts_with('-') && flags.len() > 1 && !flags[1..].chars().all(|ch| ch.is_ascii_digit()) => {
+                for flag in flags[1..].chars() {
+                    match flag {
+                        'l' => list = true,
+                        'n' => numbers = false,
+                        'r' => reverse = true,
+                        's' => substitute = true,
+                        _ => {
+                            println!("{}", help_msg);
+                            return Ok((1, 1, stdout));
+                        }
+                    }
+                }
+            },
+            _ => operands.push(arg)
+        }
+    }
+
+    // the fc command itself is already in history, it doesn't count
+    let mut entries: Vec<String> = config.history.iter().map(|entry| entry.line.clone()).collect();
+
+    if entries.last().is_some_and(|last| last == "fc" || last.starts_with("fc ")) {
+        entries.pop();
+    }
+
+    if entries.is_empty() {
+        println!("fc: history is empty");
+        return Ok((1, 1, stdout));
+    }
+
+    let mut replacement: Option<(String, String)> = None;
+
+    if substitute {
+        if let Some(idx) = operands.iter().position(|operand| operand.contains('=')) {
+            let (old, new) = operands.remove(idx).split_once('=').unwrap();
+            replacement = Some((old.to_string(), new.to_string()));
+        }
+    }
+
+    let default_first = if list { String::from("-16") } else { String::from("-1") };
+    let first_spec = operands.first().map(|operand| operand.to_string()).unwrap_or(default_first);
+
+    let first = match history::find_entry(&entries, &first_spec) {
+        Some(first) => first,
+        // listing further back than history goes just starts from the first entry
+        None if list && first_spec.starts_with('-') => 0,
+        None => {
+            println!("fc: {}: no such history entry", first_spec);
+            return Ok((1, 1, stdout));
+        }
+    };
+
+    let last = match operands.get(1) {
+        Some(spec) => match history::find_entry(&entries, spec) {
+            Some(last) => last,
+            None => {
+                println!("fc: {}: no such history entry", spec);
+                return Ok((1, 1, stdout));
+            }
+        },
+        None if list => entries.len() - 1,
+        None => first
+    };
+
+    let mut range: Vec<usize> = if first <= last { (first..=last).collect() } else { (last..=first).rev().collect() };
+
+    if reverse {
+        range.reverse();
+    }
+
+    if list {
+        for idx in range {
+            if numbers {
+                println!("{}\t{}", idx + 1, entries[idx]);
+            }
+
+            else {
+                println!("\t{}", entries[idx]);
+            }
+        }
+
+        return Ok((1, 0, stdout));
+    }
+
+    let mut commands: Vec<String> = range.iter().map(|idx| entries[*idx].clone()).collect();
+
+    if substitute {
+        if let Some((old, new)) = replacement {
+            commands = commands.iter().map(|command| command.replace(old.as_str(), new.as_str())).collect();
+        }
+    }
+
+    else {
+        let editor = editor
+            .or(config.variables.get("FCEDIT").cloned())
+            .unwrap_or(editor::default_editor(config));
+
+        commands = match editor::edit_text(config, editor.as_str(), commands.join("\n").as_str()) {
+            Ok(edited) => split_commands(&edited),
+            Err(e) => {
+                println!("fc: {}", e);
+                return Ok((1, 1, stdout));
+            }
+        };
+    }
+
+    let mut status = 0;
+
+    for command in commands.iter().filter(|command| !command.trim().is_empty()) {
+        println!("{}", command);
+        history::add_entry(config, command);
+
+        let should_continue;
+        (should_continue, status) = crate::execute_line(config, command);
+        history::finish_entry(config, command, status);
+
+        if should_continue == 0 {
+            return Ok((0, status, stdout));
+        }
+    }
+
+    Ok((1, status, stdout))
+}
+
 pub fn load_builtins(cfg: &mut Config) {
     cfg.rsh_builtins.insert(String::from("help"), help_builtin);
     cfg.rsh_builtins.insert(String::from("cd"), cd_builtin);
     cfg.rsh_builtins.insert(String::from("exit"), exit_builtin);
     cfg.rsh_builtins.insert(String::from("export"), export_builtin);
+    cfg.rsh_builtins.insert(String::from("complete"), complete_builtin);
+    cfg.rsh_builtins.insert(String::from("set"), set_builtin);
+    cfg.rsh_builtins.insert(String::from("bind"), bind_builtin);
+    cfg.rsh_builtins.insert(String::from("fc"), fc_builtin);
+    cfg.rsh_builtins.insert(String::from("abbr"), abbr_builtin);
 }
@@ -0,0 +1,376 @@
+use crate::config;
+use crate::expand;
+use crate::lexer;
+use crate::log;
+use crate::symbol_table;
+use crate::graphemes;
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+const WORD_BREAKS: &[char] = &[' ', '\t', '\n', '|', ';', '&', '<', '>', '(', ')'];
+const COMMAND_BREAKS: &[char] = &['|', ';', '&', '(', '\n'];
+
+// what the complete builtin registers for a command
+#[derive(Debug, Clone, Default)]
+pub struct CompletionSpec {
+    pub words: Vec<String>,
+    pub function: Option<String>,
+    pub commands: bool,
+    pub files: bool,
+    pub directories: bool,
+}
+
+// characters a file name can't have in a command line unless they're escaped
+const ESCAPED: &[char] = &[' ', '\t', '\n', '\\', '\'', '"', '`', '$', '|', '&', ';', '<', '>', '(', ')', '*', '?', '[', ']', '#', '!', '{', '}'];
+
+// byte offset where the word ending at ins_cur starts, a break escaped with a
+// backslash is part of the word
+pub fn word_start(line: &str, ins_cur: usize) -> usize {
+    let mut start = 0;
+    let mut chars = line[..ins_cur].char_indices();
+
+    while let Some((idx, ch)) = chars.next() {
+        if ch == '\\' {
+            chars.next();
+        }
+
+        else if WORD_BREAKS.contains(&ch) {
+            start = idx + ch.len_utf8();
+        }
+    }
+
+    start
+}
+
+// a file name the way it has to be typed, with a backslash in front of anything
+// the shell would otherwise split on or expand
+pub fn escape_name(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+
+    for ch in name.chars() {
+        if ESCAPED.contains(&ch) {
+            escaped.push('\\');
+        }
+
+        escaped.push(ch);
+    }
+
+    escaped
+}
+
+// a typed word with its backslash escapes removed
+pub fn unescape(word: &str) -> String {
+    let mut unescaped = String::with_capacity(word.len());
+    let mut chars = word.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(ch)
+        }
+    }
+
+    unescaped
+}
+
+// a word is in command position when it's the first one of a pipeline,
+// list or subshell, or when it directly follows a keyword like `if`
+pub fn is_command_position(cfg: &config::Config, before: &str) -> bool {
+    let before = before.trim_end_matches([' ', '\t']);
+
+    if before.is_empty() || before.ends_with(COMMAND_BREAKS) {
+        return true;
+    }
+
+    let prev_word = &before[word_start(before, before.len())..];
+    cfg.keywords.contains_key(prev_word)
+}
+
+fn complete_variables(cfg: &config::Config, prefix: &str) -> BTreeSet<String> {
+    cfg.variables.keys()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| format!("${}", name))
+        .collect()
+}
+
+fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false
+    }
+}
+
+pub fn complete_commands(cfg: &config::Config, prefix: &str) -> BTreeSet<String> {
+    let mut candidates = BTreeSet::new();
+
+    let names = cfg.rsh_builtins.keys()
+        .chain(cfg.functions.keys())
+        .chain(cfg.keywords.keys());
+
+    candidates.extend(names.filter(|name| name.starts_with(prefix)).cloned());
+
+    let path = match cfg.variables.get("PATH") {
+        Some(path) => path,
+        None => return candidates
+    };
+
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if name.starts_with(prefix) && is_executable(&entry.path()) {
+                candidates.insert(name);
+            }
+        }
+    }
+
+    candidates
+}
+
+pub fn complete_paths(cfg: &config::Config, word: &str, only_dirs: bool) -> BTreeSet<String> {
+    let mut candidates = BTreeSet::new();
+
+    // the part up to the last slash is kept as typed, only the rest gets completed
+    let (dir_part, file_prefix) = match word.rfind('/') {
+        Some(idx) => (&word[..idx + 1], unescape(&word[idx + 1..])),
+        None => ("", unescape(word))
+    };
+
+    let dir_to_read = if dir_part.is_empty() {
+        String::from(".")
+    }
+
+    else if let Some(rest) = dir_part.strip_prefix('~') {
+        format!("{}{}", cfg.variables.get("HOME").map(|home| home.as_str()).unwrap_or(""), unescape(rest))
+    }
+
+    else {
+        unescape(dir_part)
+    };
+
+    let entries = match fs::read_dir(&dir_to_read) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::debug(cfg, format!("could not complete in {}: {}", dir_to_read, e).as_str());
+            return candidates;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // dotfiles only show up when asked for explicitly
+        if !name.starts_with(&file_prefix) || (name.starts_with('.') && !file_prefix.starts_with('.')) {
+            continue;
+        }
+
+        let is_dir = fs::metadata(entry.path()).map(|metadata| metadata.is_dir()).unwrap_or(false);
+
+        if is_dir {
+            candidates.insert(format!("{}{}/", dir_part, escape_name(&name)));
+        }
+
+        else if !only_dirs {
+            candidates.insert(format!("{}{}", dir_part, escape_name(&name)));
+        }
+    }
+
+    candidates
+}
+
+pub fn find_command(cfg: &config::Config, name: &str) -> bool {
+    if name.contains('/') {
+        return is_executable(Path::new(name));
+    }
+
+    let path = cfg.variables.get("PATH").map(|path| path.as_str()).unwrap_or("");
+    path.split(':').any(|dir| !dir.is_empty() && is_executable(&Path::new(dir).join(name)))
+}
+
+// runs a -F completion function the same way $(...) is run and takes every line it
+// prints as a candidate, it gets the command, the word and the previous word as arguments
+fn run_completion_function(cfg: &mut config::Config, function: &str, words: &[&str], word: &str) -> Vec<String> {
+    if !cfg.functions.contains_key(function) && !find_command(cfg, function) {
+        log::debug(cfg, format!("completion function {} not found", function).as_str());
+        return Vec::new();
+    }
+
+    let prev_word = words.last().copied().unwrap_or("");
+
+    symbol_table::set_env_var("COMP_WORDS", format!("{} {}", words.join(" "), word).trim_end(), cfg);
+    symbol_table::set_env_var("COMP_CWORD", words.len().to_string().as_str(), cfg);
+
+    // quoted so that what was typed on the line is only ever passed along, never run
+    let call = format!("{} {} {} {}", function, expand::single_quote(words[0]), expand::single_quote(word), expand::single_quote(prev_word));
+
+    let output = match crate::capture_output_keep_status(cfg, &call) {
+        Ok(output) => output,
+        Err(e) => {
+            log::debug(cfg, format!("completion function {} failed: {}", function, e).as_str());
+            return Vec::new();
+        }
+    };
+
+    output.lines()
+        .map(|candidate| candidate.trim().to_string())
+        .filter(|candidate| !candidate.is_empty() && candidate.starts_with(word))
+        .collect()
+}
+
+fn complete_with_spec(cfg: &mut config::Config, spec: &CompletionSpec, words: &[&str], word: &str) -> BTreeSet<String> {
+    let mut candidates: BTreeSet<String> = spec.words.iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .cloned()
+        .collect();
+
+    if spec.commands {
+        candidates.extend(complete_commands(cfg, word));
+    }
+
+    if spec.files || spec.directories {
+        candidates.extend(complete_paths(cfg, word, !spec.files));
+    }
+
+    if let Some(function) = &spec.function {
+        candidates.extend(run_completion_function(cfg, function, words, word));
+    }
+
+    candidates
+}
+
+// candidates to replace the word that ends at ins_cur with
+pub fn complete(cfg: &mut config::Config, line: &str, ins_cur: usize) -> Vec<String> {
+    let start = word_start(line, ins_cur);
+    let word = &line[start..ins_cur];
+
+    log::debug(cfg, format!("completing word: {:?}", word).as_str());
+
+    // words of the simple command being completed, up to the current one
+    let command_start = line[..start].rfind(COMMAND_BREAKS).map(|idx| idx + 1).unwrap_or(0);
+    let tokens = lexer::tokenize_partial(&line[command_start..start]);
+    let words: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+
+    let spec = words.first().and_then(|command| cfg.completions.get(*command)).cloned();
+
+    let candidates = if let Some(var_prefix) = word.strip_prefix('$') {
+        complete_variables(cfg, var_prefix)
+    }
+
+    else if !word.contains('/') && is_command_position(cfg, &line[..start]) {
+        complete_commands(cfg, word)
+    }
+
+    else if let Some(spec) = spec {
+        symbol_table::set_env_var("COMP_LINE", line, cfg);
+        symbol_table::set_env_var("COMP_POINT", ins_cur.to_string().as_str(), cfg);
+
+        complete_with_spec(cfg, &spec, &words, word)
+    }
+
+    else {
+        complete_paths(cfg, word, false)
+    };
+
+    candidates.into_iter().collect()
+}
+
+pub fn common_prefix(candidates: &[String]) -> String {
+    let mut prefix = match candidates.first() {
+        Some(first) => first.clone(),
+        None => return String::new()
+    };
+
+    for candidate in &candidates[1..] {
+        while !candidate.starts_with(prefix.as_str()) {
+            prefix.pop();
+        }
+    }
+
+    prefix
+}
+
+// what gets shown in the listing: paths only show their last component, without
+// the escapes that were added for the line
+pub fn display_name(candidate: &str) -> String {
+    let trimmed = candidate.strip_suffix('/').unwrap_or(candidate);
+
+    match trimmed.rfind('/') {
+        Some(idx) => unescape(&candidate[idx + 1..]),
+        None => unescape(candidate)
+    }
+}
+
+// lays candidates out in columns, ordered top to bottom like ls does
+pub fn format_columns(candidates: &[String], terminal_cols: usize) -> Vec<String> {
+    let names: Vec<String> = candidates.iter().map(|candidate| display_name(candidate)).collect();
+    let col_width = names.iter().map(|name| graphemes::display_width(name)).max().unwrap_or(0) + 2;
+
+    let num_cols = (terminal_cols / col_width).max(1);
+    let num_rows = names.len().div_ceil(num_cols);
+
+    let mut rows = Vec::new();
+
+    for row in 0..num_rows {
+        let mut text = String::new();
+
+        for col in 0..num_cols {
+            let idx = col * num_rows + row;
+            if idx >= names.len() {
+                break;
+            }
+
+            let name = names[idx].as_str();
+            text.push_str(name);
+
+            if (col + 1) * num_rows + row < names.len() {
+                text.push_str(" ".repeat(col_width - graphemes::display_width(name)).as_str());
+            }
+        }
+
+        rows.push(text);
+    }
+
+    rows
+}
+
+// the complete invocation that registers spec, as printed by complete -p
+pub fn format_spec(name: &str, spec: &CompletionSpec) -> String {
+    let mut flags = String::new();
+
+    if spec.commands {
+        flags.push('c');
+    }
+
+    if spec.directories {
+        flags.push('d');
+    }
+
+    if spec.files {
+        flags.push('f');
+    }
+
+    let mut text = String::from("complete");
+
+    if !flags.is_empty() {
+        text.push_str(format!(" -{}", flags).as_str());
+    }
+
+    if !spec.words.is_empty() {
+        text.push_str(format!(" -W '{}'", spec.words.join(" ")).as_str());
+    }
+
+    if let Some(function) = &spec.function {
+        text.push_str(format!(" -F {}", function).as_str());
+    }
+
+    text.push_str(format!(" {}", name).as_str());
+    text
+}
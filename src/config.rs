@@ -1,13 +1,17 @@
 use std::collections::HashMap;
 use std::fs::File;
 use crate::builtins;
-use crate::main_loop;
+use crate::run_lines;
 use crate::symbol_table;
 use crate::keywords;
+use crate::completion::CompletionSpec;
+use crate::history::HistoryEntry;
+use crate::keymap;
 
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
 use std::path::Path;
 use std::io;
+use termion::event::Key;
 
 pub enum LogLevel {
     Debug,
@@ -18,37 +22,72 @@ pub enum LogLevel {
 
 pub struct Config {
     pub rsh_builtins: HashMap<String, fn(&Vec<&String>, &mut Config) -> Result<(i32, i32, i32), String>>,
+    pub completions: HashMap<String, CompletionSpec>,
+    pub abbreviations: HashMap<String, String>,
     pub variables: HashMap<String, String>,
     pub functions: HashMap<String, String>,
     pub keywords: HashMap<String, fn(&Vec<&String>, &mut Config) -> Result<(i32, i32), String>>,
+    pub history: Vec<HistoryEntry>,
+    pub kill_ring: Vec<String>,
+    pub options: HashMap<String, bool>,
+    pub keymap: HashMap<Vec<Key>, String>,
     pub log_level: LogLevel,
     pub log_file: RawFd,
     pub stdin_to_execute: RawFd
 }
 
+// options that can be turned on and off through set -o / set +o
+fn load_options(cfg: &mut Config) {
+    cfg.options.insert(String::from("emacs"), true);
+    cfg.options.insert(String::from("vi"), false);
+    cfg.options.insert(String::from("transientprompt"), false);
+    cfg.options.insert(String::from("ignoreeof"), false);
+}
+
+pub fn option_enabled(cfg: &Config, name: &str) -> bool {
+    cfg.options.get(name).copied().unwrap_or(false)
+}
+
 pub fn load_config() -> Result<Config, String> {
     let mut loc_config = Config {
         rsh_builtins: HashMap::new(),
+        completions: HashMap::new(),
+        abbreviations: HashMap::new(),
         variables: HashMap::new(),
         functions: HashMap::new(),
         keywords: HashMap::new(),
+        history: Vec::new(),
+        kill_ring: Vec::new(),
+        options: HashMap::new(),
+        keymap: HashMap::new(),
         log_level: LogLevel::Critical,
         log_file: io::stderr().as_raw_fd(),
-        stdin_to_execute: -1,
+        stdin_to_execute: io::stdin().as_raw_fd(),
     };
     
 
+    load_options(&mut loc_config);
     keywords::load_keywords(&mut loc_config);
     builtins::load_builtins(&mut loc_config);
     symbol_table::load_variables(&mut loc_config);
+    keymap::load_default_keymap(&mut loc_config);
+    keymap::load_inputrc(&mut loc_config);
 
-    let rshconfig_path = Path::new(loc_config.variables.get("HOME").unwrap()).join(Path::new(".rshconfig"));
-    let rshconfig_file = File::open(rshconfig_path).unwrap();
-    
-    loc_config.stdin_to_execute = rshconfig_file.as_raw_fd();
-    
-    main_loop(&mut loc_config);
-    
     Ok(loc_config)
 }
 
+// runs ~/.rshconfig, only interactive shells do, returns whether to keep going and the status
+pub fn load_rshconfig(cfg: &mut Config) -> (i32, i32) {
+    let home = match cfg.variables.get("HOME") {
+        Some(home) => home.clone(),
+        None => return (1, 0)
+    };
+
+    let rshconfig_path = Path::new(&home).join(Path::new(".rshconfig"));
+
+    match File::open(&rshconfig_path) {
+        Ok(rshconfig_file) => run_lines(cfg, rshconfig_file.into_raw_fd(), &rshconfig_path.to_string_lossy()),
+        Err(_) => (1, 0)
+    }
+}
+
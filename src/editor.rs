@@ -0,0 +1,71 @@
+use crate::config;
+use crate::log;
+
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::{self, Command};
+
+// $VISUAL, then $EDITOR, then plain vi
+pub fn default_editor(cfg: &config::Config) -> String {
+    for name in ["VISUAL", "EDITOR"] {
+        if let Some(editor) = cfg.variables.get(name) {
+            if !editor.trim().is_empty() {
+                return editor.clone();
+            }
+        }
+    }
+
+    String::from("vi")
+}
+
+// a new file only the user can read, with a random name so that nobody else can put
+// a symlink or a file of their own where it's going to be
+fn create_temp_file(tmp_dir: &str) -> Result<(String, fs::File), String> {
+    let mut last_error = String::new();
+
+    for _ in 0..100 {
+        let suffix = RandomState::new().build_hasher().finish();
+        let path = format!("{}/rsh-edit-{}-{:016x}.sh", tmp_dir.trim_end_matches('/'), process::id(), suffix);
+
+        match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => last_error = format!("{}: {}", path, e),
+            Err(e) => return Err(format!("{}: {}", path, e))
+        }
+    }
+
+    Err(last_error)
+}
+
+// opens text in editor through a temporary file and returns what was saved, the
+// editor may have arguments of its own like `code -w`
+pub fn edit_text(cfg: &config::Config, editor: &str, text: &str) -> Result<String, String> {
+    let tmp_dir = cfg.variables.get("TMPDIR").cloned().unwrap_or(env::temp_dir().to_string_lossy().to_string());
+    let (path, mut file) = create_temp_file(&tmp_dir)?;
+
+    if let Err(e) = writeln!(file, "{}", text) {
+        fs::remove_file(&path).ok();
+        return Err(format!("{}: {}", path, e));
+    }
+
+    drop(file);
+
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+
+    log::debug(cfg, format!("editing {} with {}", path, editor).as_str());
+
+    let result = match Command::new(program).args(words).arg(&path).status() {
+        Ok(status) if status.success() => fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e)),
+        Ok(status) => Err(format!("{}: exited with {}", program, status)),
+        Err(e) => Err(format!("{}: {}", program, e))
+    };
+
+    fs::remove_file(&path).ok();
+
+    result.map(|edited| edited.trim_end_matches('\n').to_string())
+}
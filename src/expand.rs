@@ -0,0 +1,776 @@
+use crate::config;
+use crate::glob;
+use crate::lexer;
+use crate::parser;
+
+// value of a variable or of one of the special parameters, None when it's unset
+fn lookup(cfg: &config::Config, name: &str) -> Option<String> {
+    match name {
+        "$" => Some(std::process::id().to_string()),
+        "0" => Some(cfg.variables.get("0").cloned().unwrap_or(String::from("rsh"))),
+        _ => cfg.variables.get(name).cloned()
+    }
+}
+
+// length of the parameter name at the start of text, 0 when there isn't one
+fn name_len(text: &str) -> usize {
+    match text.chars().next() {
+        Some(ch) if "?$#!@*-".contains(ch) || ch.is_ascii_digit() => 1,
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => {
+            text.find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_').unwrap_or(text.len())
+        },
+        _ => 0
+    }
+}
+
+// same as name_len, but between braces a positional parameter can have more than one digit
+fn braced_name_len(text: &str) -> usize {
+    if text.starts_with(|ch: char| ch.is_ascii_digit()) {
+        return text.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(text.len());
+    }
+
+    name_len(text)
+}
+
+// byte offsets of every char boundary in text, the end included
+fn boundaries(text: &str) -> Vec<usize> {
+    text.char_indices().map(|(idx, _)| idx).chain([text.len()]).collect()
+}
+
+// ${name#pattern} and ${name##pattern}
+fn remove_prefix(value: &str, pattern: &str, longest: bool) -> String {
+    let mut ends = boundaries(value);
+
+    if longest {
+        ends.reverse();
+    }
+
+    match ends.into_iter().find(|end| glob::glob_match(pattern, &value[..*end])) {
+        Some(end) => value[end..].to_string(),
+        None => value.to_string()
+    }
+}
+
+// ${name%pattern} and ${name%%pattern}
+fn remove_suffix(value: &str, pattern: &str, longest: bool) -> String {
+    let mut starts = boundaries(value);
+
+    if !longest {
+        starts.reverse();
+    }
+
+    match starts.into_iter().find(|start| glob::glob_match(pattern, &value[*start..])) {
+        Some(start) => value[..start].to_string(),
+        None => value.to_string()
+    }
+}
+
+// where the pattern of ${name/pattern/replacement} ends, the first / that isn't
+// escaped or quoted
+fn pattern_end(text: &str) -> usize {
+    let mut pos = 0;
+
+    while let Some(ch) = text[pos..].chars().next() {
+        match ch {
+            '/' => return pos,
+            '\\' => pos += 1 + text[pos + 1..].chars().next().map_or(0, |next| next.len_utf8()),
+            '\'' | '"' | '`' | '$' => pos = lexer::part_end(text, pos).max(pos + 1),
+            _ => pos += ch.len_utf8()
+        }
+    }
+
+    text.len()
+}
+
+// ${name/pattern/replacement}, operation is what follows the first /. a second /
+// replaces every match, # only matches at the start and % only at the end
+fn replace_pattern(cfg: &mut config::Config, value: &str, operation: &str) -> Result<String, String> {
+    let (all, anchor, rest) = match operation.chars().next() {
+        Some('/') => (true, None, &operation[1..]),
+        Some(anchor @ ('#' | '%')) => (false, Some(anchor), &operation[1..]),
+        _ => (false, None, operation)
+    };
+
+    let end = pattern_end(rest);
+    let pattern = expand_pattern(cfg, &rest[..end])?;
+    let replacement = match rest.get(end + 1..) {
+        Some(replacement) => expand_word(cfg, replacement)?,
+        None => String::new()
+    };
+
+    if pattern.is_empty() {
+        return Ok(value.to_string());
+    }
+
+    let offsets = boundaries(value);
+
+    match anchor {
+        Some('#') => {
+            return Ok(match offsets.iter().rev().find(|end| glob::glob_match(&pattern, &value[..**end])) {
+                Some(end) => format!("{}{}", replacement, &value[*end..]),
+                None => value.to_string()
+            });
+        },
+        Some(_) => {
+            return Ok(match offsets.iter().find(|start| glob::glob_match(&pattern, &value[**start..])) {
+                Some(start) => format!("{}{}", &value[..*start], replacement),
+                None => value.to_string()
+            });
+        },
+        None => {}
+    }
+
+    let mut replaced = String::new();
+    let mut idx = 0;
+
+    while idx + 1 < offsets.len() {
+        let start = offsets[idx];
+
+        // the longest match starting here, an empty one doesn't count
+        match (idx + 1..offsets.len()).rev().find(|end| glob::glob_match(&pattern, &value[start..offsets[*end]])) {
+            Some(end) => {
+                replaced.push_str(&replacement);
+                idx = end;
+
+                if !all {
+                    replaced.push_str(&value[offsets[idx]..]);
+                    return Ok(replaced);
+                }
+            },
+            None => {
+                replaced.push_str(&value[start..offsets[idx + 1]]);
+                idx += 1;
+            }
+        }
+    }
+
+    Ok(replaced)
+}
+
+// ${name:offset} and ${name:offset:length}, a negative offset counts from the end
+// and so does a negative length
+fn substring(cfg: &mut config::Config, value: &str, spec: &str) -> Result<String, String> {
+    let spec = expand_word(cfg, spec)?;
+    let (offset, length) = match spec.split_once(':') {
+        Some((offset, length)) => (offset, Some(length)),
+        None => (spec.as_str(), None)
+    };
+
+    let parse = |number: &str| number.trim().parse::<i64>().map_err(|_| format!("{}: arithmetic syntax error", number.trim()));
+
+    let chars: Vec<char> = value.chars().collect();
+    let total = chars.len() as i64;
+
+    let mut start = parse(offset)?;
+
+    if start < 0 {
+        start += total;
+    }
+
+    if start < 0 || start > total {
+        return Ok(String::new());
+    }
+
+    let end = match length {
+        Some(length) => {
+            let length = parse(length)?;
+
+            if length < 0 && total + length < start {
+                return Err(format!("{}: substring expression < 0", length));
+            }
+
+            if length < 0 {
+                total + length
+            }
+
+            else {
+                (start + length).min(total)
+            }
+        },
+        None => total
+    };
+
+    Ok(chars[start as usize..end as usize].iter().collect())
+}
+
+// ${name^^}, ${name^}, ${name,,} and ${name,}, with a pattern only the chars it
+// matches change
+fn change_case(value: &str, pattern: &str, upper: bool, all: bool) -> String {
+    let mut changed = String::with_capacity(value.len());
+
+    for (idx, ch) in value.chars().enumerate() {
+        if (all || idx == 0) && (pattern.is_empty() || glob::glob_match(pattern, &ch.to_string())) {
+            if upper {
+                changed.extend(ch.to_uppercase());
+            }
+
+            else {
+                changed.extend(ch.to_lowercase());
+            }
+        }
+
+        else {
+            changed.push(ch);
+        }
+    }
+
+    changed
+}
+
+// what ${inner} expands to
+fn expand_braced(cfg: &mut config::Config, inner: &str) -> Result<String, String> {
+    let bad_substitution = || format!("${{{}}}: bad substitution", inner);
+
+    // ${#name} is the length of the value, ${#} on its own is $#
+    if let Some(name) = inner.strip_prefix('#').filter(|name| !name.is_empty()) {
+        if braced_name_len(name) != name.len() {
+            return Err(bad_substitution());
+        }
+
+        return Ok(lookup(cfg, name).unwrap_or_default().chars().count().to_string());
+    }
+
+    let len = braced_name_len(inner);
+
+    if len == 0 {
+        return Err(bad_substitution());
+    }
+
+    let (name, operation) = inner.split_at(len);
+    let value = lookup(cfg, name);
+
+    // with the colon an empty value counts as unset too
+    let (colon, rest) = match operation.strip_prefix(':') {
+        Some(rest) if rest.starts_with(['-', '=', '?', '+']) => (true, rest),
+        _ => (false, operation)
+    };
+
+    if let Some(op) = rest.chars().next().filter(|op| "-=?+".contains(*op)) {
+        let word = &rest[1..];
+        let set = value.as_ref().is_some_and(|value| !colon || !value.is_empty());
+
+        return match op {
+            '-' | '=' | '?' if set => Ok(value.unwrap()),
+            '-' => expand_word(cfg, word),
+            '=' => {
+                if !parser::is_name(name) {
+                    return Err(format!("${}: cannot assign in this way", name));
+                }
+
+                let value = expand_word(cfg, word)?;
+                cfg.variables.insert(name.to_string(), value.clone());
+
+                Ok(value)
+            },
+            '?' => match expand_word(cfg, word)? {
+                message if message.is_empty() => Err(format!("{}: parameter null or not set", name)),
+                message => Err(format!("{}: {}", name, message))
+            },
+            '+' if set => expand_word(cfg, word),
+            _ => Ok(String::new())
+        };
+    }
+
+    let value = value.unwrap_or_default();
+
+    if operation.is_empty() {
+        Ok(value)
+    }
+
+    else if let Some(spec) = operation.strip_prefix(':') {
+        substring(cfg, &value, spec)
+    }
+
+    else if let Some(pattern) = operation.strip_prefix("##") {
+        Ok(remove_prefix(&value, &expand_pattern(cfg, pattern)?, true))
+    }
+
+    else if let Some(pattern) = operation.strip_prefix('#') {
+        Ok(remove_prefix(&value, &expand_pattern(cfg, pattern)?, false))
+    }
+
+    else if let Some(pattern) = operation.strip_prefix("%%") {
+        Ok(remove_suffix(&value, &expand_pattern(cfg, pattern)?, true))
+    }
+
+    else if let Some(pattern) = operation.strip_prefix('%') {
+        Ok(remove_suffix(&value, &expand_pattern(cfg, pattern)?, false))
+    }
+
+    else if let Some(rest) = operation.strip_prefix('/') {
+        replace_pattern(cfg, &value, rest)
+    }
+
+    else if let Some(pattern) = operation.strip_prefix("^^") {
+        Ok(change_case(&value, &expand_pattern(cfg, pattern)?, true, true))
+    }
+
+    else if let Some(pattern) = operation.strip_prefix('^') {
+        Ok(change_case(&value, &expand_pattern(cfg, pattern)?, true, false))
+    }
+
+    else if let Some(pattern) = operation.strip_prefix(",,") {
+        Ok(change_case(&value, &expand_pattern(cfg, pattern)?, false, true))
+    }
+
+    else if let Some(pattern) = operation.strip_prefix(',') {
+        Ok(change_case(&value, &expand_pattern(cfg, pattern)?, false, false))
+    }
+
+    else {
+        Err(bad_substitution())
+    }
+}
+
+// expands the $ at the start of text, returns what it expands to and how much of
+// text that took
+fn expand_dollar(cfg: &mut config::Config, text: &str) -> Result<(String, usize), String> {
+    if text.starts_with("$(") {
+        let end = lexer::part_end(text, 0);
+        let output = crate::capture_output(cfg, &text[2..end - 1])?;
+
+        return Ok((output, end));
+    }
+
+    if text.starts_with("${") {
+        let end = lexer::part_end(text, 0);
+        let inner = &text[2..end];
+
+        return Ok((expand_braced(cfg, inner.strip_suffix('}').unwrap_or(inner))?, end));
+    }
+
+    let len = name_len(&text[1..]);
+
+    if len == 0 {
+        return Ok((String::from("$"), 1));
+    }
+
+    Ok((lookup(cfg, &text[1..1 + len]).unwrap_or_default(), 1 + len))
+}
+
+// the value of up to max digits in radix at the start of chars, None when there's
+// not even one
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>, radix: u32, max: usize) -> Option<u32> {
+    let mut value = None;
+
+    for _ in 0..max {
+        let digit = match chars.peek().and_then(|ch| ch.to_digit(radix)) {
+            Some(digit) => digit,
+            None => break
+        };
+
+        chars.next();
+        value = Some(value.unwrap_or(0) * radix + digit);
+    }
+
+    value
+}
+
+// the inside of $'...' with its escapes replaced, the same ones bash knows
+fn ansi_c_unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('a') => '\x07',
+            Some('b') => '\x08',
+            Some('e') | Some('E') => '\x1b',
+            Some('f') => '\x0c',
+            Some('v') => '\x0b',
+            Some(ch @ ('\\' | '\'' | '"' | '?')) => ch,
+            Some(ch @ '0'..='7') => {
+                let mut digits = ch.to_string();
+
+                while digits.len() < 3 && chars.peek().is_some_and(|ch| ch.is_digit(8)) {
+                    digits.push(chars.next().unwrap());
+                }
+
+                char::from_u32(u32::from_str_radix(&digits, 8).unwrap()).unwrap_or(char::REPLACEMENT_CHARACTER)
+            },
+            Some(ch @ ('x' | 'u' | 'U')) => {
+                let max = match ch {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8
+                };
+
+                match take_digits(&mut chars, 16, max) {
+                    Some(value) => char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER),
+                    None => {
+                        unescaped.push('\\');
+                        ch
+                    }
+                }
+            },
+            Some('c') => match chars.next() {
+                Some(ch) => char::from_u32(ch.to_ascii_uppercase() as u32 ^ 0x40).unwrap_or(ch),
+                None => {
+                    unescaped.push('\\');
+                    'c'
+                }
+            },
+            Some(ch) => {
+                unescaped.push('\\');
+                ch
+            },
+            None => '\\'
+        };
+
+        unescaped.push(escaped);
+    }
+
+    unescaped
+}
+
+// the command inside `...`, a backslash there only escapes $, ` and \ and also "
+// when the backquotes are between double quotes
+fn backquoted_command(text: &str, in_double_quotes: bool) -> String {
+    let mut command = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match chars.peek() {
+            Some(&next) if ch == '\\' && (matches!(next, '$' | '`' | '\\') || (in_double_quotes && next == '"')) => {
+                command.push(next);
+                chars.next();
+            },
+            _ => command.push(ch)
+        }
+    }
+
+    command
+}
+
+// a word the way a command gets it, quotes removed and $name, $(...) and `...`
+// replaced with their values. nothing inside single quotes is expanded and between
+// double quotes a backslash only escapes $, `, ", \ and a newline. for a pattern
+// whatever was quoted is escaped so that it only matches itself
+fn expand(cfg: &mut config::Config, word: &str, pattern: bool) -> Result<String, String> {
+    // text that came from inside quotes
+    let quoted = |text: &str| if pattern { glob::escape(text) } else { text.to_string() };
+
+    let mut expanded = String::new();
+    let mut in_double_quotes = false;
+    let mut pos = 0;
+
+    while let Some(ch) = word[pos..].chars().next() {
+        match ch {
+            '\\' => {
+                let next = word[pos + 1..].chars().next();
+
+                match next {
+                    // a backslash before a newline joins the lines
+                    Some('\n') => {},
+                    Some(next) if !in_double_quotes || matches!(next, '$' | '`' | '"' | '\\') => expanded.push_str(&quoted(&next.to_string())),
+                    Some(next) => expanded.push_str(&quoted(&format!("\\{}", next))),
+                    None => expanded.push_str(&quoted("\\"))
+                }
+
+                pos += 1 + next.map_or(0, |next| next.len_utf8());
+            },
+            '\'' if !in_double_quotes => {
+                let end = lexer::part_end(word, pos);
+
+                expanded.push_str(&quoted(word[pos + 1..end].trim_end_matches('\'')));
+                pos = end;
+            },
+            '"' => {
+                in_double_quotes = !in_double_quotes;
+                pos += 1;
+            },
+            '`' => {
+                let end = lexer::part_end(word, pos);
+                let inside = &word[pos + 1..end];
+                let command = backquoted_command(inside.strip_suffix('`').unwrap_or(inside), in_double_quotes);
+
+                let output = crate::capture_output(cfg, &command)?;
+
+                expanded.push_str(&if in_double_quotes { quoted(&output) } else { output });
+                pos = end;
+            },
+            '$' if !in_double_quotes && word[pos..].starts_with("$'") => {
+                let end = lexer::part_end(word, pos);
+                let inside = &word[pos + 2..end];
+
+                expanded.push_str(&quoted(&ansi_c_unescape(inside.strip_suffix('\'').unwrap_or(inside))));
+                pos = end;
+            },
+            '$' => {
+                let (value, len) = expand_dollar(cfg, &word[pos..])?;
+
+                expanded.push_str(&if in_double_quotes { quoted(&value) } else { value });
+                pos += len;
+            },
+            _ if in_double_quotes => {
+                expanded.push_str(&quoted(&ch.to_string()));
+                pos += ch.len_utf8();
+            },
+            _ => {
+                expanded.push(ch);
+                pos += ch.len_utf8();
+            }
+        }
+    }
+
+    Ok(expanded)
+}
+
+pub fn expand_word(cfg: &mut config::Config, word: &str) -> Result<String, String> {
+    expand(cfg, word, false)
+}
+
+// a word used as a pattern, like in ${name#pattern}
+fn expand_pattern(cfg: &mut config::Config, word: &str) -> Result<String, String> {
+    expand(cfg, word, true)
+}
+
+// text in single quotes, the shell reads it back as exactly text without expanding
+// anything in it
+pub fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+// the words of a command expanded, a word that was nothing but an unquoted
+// expansion of an empty value goes away instead of being an empty argument
+pub fn expand_words(cfg: &mut config::Config, words: &[parser::Word]) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+
+    for word in words {
+        let value = expand_word(cfg, &word.text)?;
+
+        if value.is_empty() && !word.text.contains(['\'', '"']) {
+            continue;
+        }
+
+        expanded.push(value);
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    // nothing from the environment or the user's files, only the given variables
+    fn config(variables: &[(&str, &str)]) -> config::Config {
+        config::Config {
+            rsh_builtins: HashMap::new(),
+            completions: HashMap::new(),
+            abbreviations: HashMap::new(),
+            variables: variables.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            functions: HashMap::new(),
+            keywords: HashMap::new(),
+            history: Vec::new(),
+            kill_ring: Vec::new(),
+            options: HashMap::new(),
+            keymap: HashMap::new(),
+            log_level: config::LogLevel::Critical,
+            log_file: 2,
+            stdin_to_execute: 0,
+        }
+    }
+
+    fn expand_with(variables: &[(&str, &str)], word: &str) -> Result<String, String> {
+        expand_word(&mut config(variables), word)
+    }
+
+    #[test]
+    fn prefix_and_suffix() {
+        assert_eq!(remove_prefix("a/b/c", "*/", false), "b/c");
+        assert_eq!(remove_prefix("a/b/c", "*/", true), "c");
+        assert_eq!(remove_prefix("abc", "x*", true), "abc");
+        assert_eq!(remove_prefix("abc", "*", false), "abc");
+        assert_eq!(remove_prefix("abc", "*", true), "");
+
+        assert_eq!(remove_suffix("file.tar.gz", ".*", false), "file.tar");
+        assert_eq!(remove_suffix("file.tar.gz", ".*", true), "file");
+        assert_eq!(remove_suffix("abc", "[0-9]", false), "abc");
+        assert_eq!(remove_suffix("héé", "é", false), "hé");
+    }
+
+    #[test]
+    fn prefix_and_suffix_through_expansion() {
+        let variables = [("path", "/usr/local/bin"), ("pat", "*/")];
+
+        assert_eq!(expand_with(&variables, "${path#*/}").unwrap(), "usr/local/bin");
+        assert_eq!(expand_with(&variables, "${path##*/}").unwrap(), "bin");
+        assert_eq!(expand_with(&variables, "${path%/*}").unwrap(), "/usr/local");
+        assert_eq!(expand_with(&variables, "${path%%/*}").unwrap(), "");
+        assert_eq!(expand_with(&variables, "${path##$pat}").unwrap(), "bin");
+
+        // quoted, the * only matches itself
+        assert_eq!(expand_with(&variables, "${path##\"$pat\"}").unwrap(), "/usr/local/bin");
+        assert_eq!(expand_with(&variables, "${path#'/usr'}").unwrap(), "/local/bin");
+    }
+
+    #[test]
+    fn change_case_of_chars() {
+        assert_eq!(change_case("hello world", "", true, true), "HELLO WORLD");
+        assert_eq!(change_case("hello", "", true, false), "Hello");
+        assert_eq!(change_case("HELLO", "", false, true), "hello");
+        assert_eq!(change_case("HELLO", "", false, false), "hELLO");
+        assert_eq!(change_case("hello", "[aeiou]", true, true), "hEllO");
+        assert_eq!(change_case("hello", "[aeiou]", true, false), "hello");
+        assert_eq!(change_case("straße", "", true, true), "STRASSE");
+
+        assert_eq!(expand_with(&[("x", "abc")], "${x^^[ab]}").unwrap(), "ABc");
+        assert_eq!(expand_with(&[("x", "ABC")], "${x,}").unwrap(), "aBC");
+    }
+
+    #[test]
+    fn substrings() {
+        let variables = [("x", "abcdef")];
+
+        assert_eq!(expand_with(&variables, "${x:2}").unwrap(), "cdef");
+        assert_eq!(expand_with(&variables, "${x:2:3}").unwrap(), "cde");
+        assert_eq!(expand_with(&variables, "${x:1:-1}").unwrap(), "bcde");
+        assert_eq!(expand_with(&variables, "${x: -2}").unwrap(), "ef");
+        assert_eq!(expand_with(&variables, "${x: -2:1}").unwrap(), "e");
+        assert_eq!(expand_with(&variables, "${x:0:100}").unwrap(), "abcdef");
+
+        // out of range is empty, a length ending before the offset is an error
+        assert_eq!(expand_with(&variables, "${x:10}").unwrap(), "");
+        assert_eq!(expand_with(&variables, "${x: -10}").unwrap(), "");
+        assert_eq!(expand_with(&variables, "${x:4:-3}").unwrap_err(), "-3: substring expression < 0");
+        assert_eq!(expand_with(&variables, "${x:a}").unwrap_err(), "a: arithmetic syntax error");
+
+        assert_eq!(expand_with(&[("x", "héllo")], "${x:1:2}").unwrap(), "él");
+    }
+
+    #[test]
+    fn substring_is_not_a_default() {
+        let variables = [("x", "abcdef"), ("empty", "")];
+
+        // without the space :- is the default value operator
+        assert_eq!(expand_with(&variables, "${x:-2}").unwrap(), "abcdef");
+        assert_eq!(expand_with(&variables, "${unset:-2}").unwrap(), "2");
+        assert_eq!(expand_with(&variables, "${empty:-2}").unwrap(), "2");
+        assert_eq!(expand_with(&variables, "${empty-2}").unwrap(), "");
+        assert_eq!(expand_with(&variables, "${unset: -2}").unwrap(), "");
+    }
+
+    #[test]
+    fn replacements() {
+        let variables = [("x", "a-b-c")];
+
+        assert_eq!(expand_with(&variables, "${x/-/+}").unwrap(), "a+b-c");
+        assert_eq!(expand_with(&variables, "${x//-/+}").unwrap(), "a+b+c");
+        assert_eq!(expand_with(&variables, "${x//-}").unwrap(), "abc");
+        assert_eq!(expand_with(&variables, "${x/[bc]/X}").unwrap(), "a-X-c");
+        assert_eq!(expand_with(&variables, "${x/-*/}").unwrap(), "a");
+        assert_eq!(expand_with(&variables, "${x/z/y}").unwrap(), "a-b-c");
+        assert_eq!(expand_with(&variables, "${x//}").unwrap(), "a-b-c");
+        assert_eq!(expand_with(&variables, "${x/'-'/\\/}").unwrap(), "a/b-c");
+    }
+
+    #[test]
+    fn anchored_replacements() {
+        let variables = [("x", "aXbXa"), ("y", "aaa")];
+
+        assert_eq!(expand_with(&variables, "${x/#a/_}").unwrap(), "_XbXa");
+        assert_eq!(expand_with(&variables, "${x/%a/_}").unwrap(), "aXbX_");
+        assert_eq!(expand_with(&variables, "${x/#X/_}").unwrap(), "aXbXa");
+        assert_eq!(expand_with(&variables, "${x/%X/_}").unwrap(), "aXbXa");
+
+        // anchored matches are the longest ones
+        assert_eq!(expand_with(&variables, "${x/#a*X/_}").unwrap(), "_a");
+        assert_eq!(expand_with(&variables, "${x/%X*/_}").unwrap(), "a_");
+        assert_eq!(expand_with(&variables, "${y/#a/b}").unwrap(), "baa");
+        assert_eq!(expand_with(&variables, "${y/%a/b}").unwrap(), "aab");
+    }
+
+    #[test]
+    fn single_quotes() {
+        let variables = [("x", "value")];
+
+        assert_eq!(expand_with(&variables, r#"'$x \ "`a`'"#).unwrap(), r#"$x \ "`a`"#);
+        assert_eq!(expand_with(&variables, "''").unwrap(), "");
+        assert_eq!(expand_with(&variables, r#""'$x'""#).unwrap(), "'value'");
+    }
+
+    #[test]
+    fn double_quotes() {
+        let variables = [("x", "a  b")];
+
+        assert_eq!(expand_with(&variables, r#""$x""#).unwrap(), "a  b");
+        assert_eq!(expand_with(&variables, r#""${x}c""#).unwrap(), "a  bc");
+
+        // a backslash only escapes $ ` " \ and a newline, before anything else it stays
+        assert_eq!(expand_with(&variables, r#""\$x \` \" \\ \a \n""#).unwrap(), r#"$x ` " \ \a \n"#);
+        assert_eq!(expand_with(&variables, "\"a\\\nb\"").unwrap(), "ab");
+        assert_eq!(expand_with(&variables, r#""$'a'""#).unwrap(), "$'a'");
+    }
+
+    #[test]
+    fn unquoted_backslashes() {
+        let variables = [("x", "value")];
+
+        assert_eq!(expand_with(&variables, r"\a\$x\\").unwrap(), r"a$x\");
+        assert_eq!(expand_with(&variables, "a\\\nb").unwrap(), "ab");
+        assert_eq!(expand_with(&variables, r#"\'\""#).unwrap(), "'\"");
+        assert_eq!(expand_with(&variables, "a\\").unwrap(), "a\\");
+    }
+
+    #[test]
+    fn adjacent_parts_are_joined() {
+        let variables = [("x", "value")];
+
+        assert_eq!(expand_with(&variables, r#"a'b c'"$x"$'\t'd${x}"#).unwrap(), "ab cvalue\tdvalue");
+        assert_eq!(expand_with(&variables, r#"pre"fix"'-'$x"#).unwrap(), "prefix-value");
+    }
+
+    #[test]
+    fn backquotes() {
+        assert_eq!(backquoted_command(r"echo \$x \` \\ \a", false), r"echo $x ` \ \a");
+
+        // \" is only unescaped when the backquotes are between double quotes
+        assert_eq!(backquoted_command(r#"echo \"a\""#, false), r#"echo \"a\""#);
+        assert_eq!(backquoted_command(r#"echo \"a\""#, true), r#"echo "a""#);
+    }
+
+    #[test]
+    fn ansi_c_escapes() {
+        assert_eq!(ansi_c_unescape(r#"a\nb\tc\\d\'e\"f\?"#), "a\nb\tc\\d'e\"f?");
+        assert_eq!(ansi_c_unescape(r"\a\b\e\E\f\v\r"), "\x07\x08\x1b\x1b\x0c\x0b\r");
+
+        // at most three octal digits
+        assert_eq!(ansi_c_unescape(r"\101\0"), "A\0");
+        assert_eq!(ansi_c_unescape(r"\1011\8"), "A1\\8");
+
+        // at most two hex digits for \x, four for \u and eight for \U
+        assert_eq!(ansi_c_unescape(r"\x41\x4g\x414"), "A\x04gA4");
+        assert_eq!(ansi_c_unescape(r"é\U0001F600"), "é😀");
+        assert_eq!(ansi_c_unescape(r"\xg"), r"\xg");
+
+        // control characters
+        assert_eq!(ansi_c_unescape(r"\ca\cA\c[\c?"), "\x01\x01\x1b\x7f");
+        assert_eq!(ansi_c_unescape(r"\c"), r"\c");
+
+        // unknown escapes and a trailing backslash stay as they are
+        assert_eq!(ansi_c_unescape(r"\q\"), r"\q\");
+    }
+
+    #[test]
+    fn ansi_c_quotes_in_words() {
+        assert_eq!(expand_with(&[], r"$'it\'s'").unwrap(), "it's");
+        assert_eq!(expand_with(&[], r"$'\x41'B").unwrap(), "AB");
+    }
+
+    #[test]
+    fn bad_substitutions() {
+        assert_eq!(expand_with(&[], "${x!}").unwrap_err(), "${x!}: bad substitution");
+        assert_eq!(expand_with(&[], "${}").unwrap_err(), "${}: bad substitution");
+    }
+}
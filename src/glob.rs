@@ -0,0 +1,207 @@
+// shell pattern matching, * ? [...] and backslash escapes, the way patterns in
+// ${name#pattern} and the like are matched
+
+// whether ch is in the class named by [:name:]
+fn in_class(name: &str, ch: char) -> bool {
+    match name {
+        "alnum" => ch.is_alphanumeric(),
+        "alpha" => ch.is_alphabetic(),
+        "blank" => ch == ' ' || ch == '\t',
+        "digit" => ch.is_ascii_digit(),
+        "lower" => ch.is_lowercase(),
+        "upper" => ch.is_uppercase(),
+        "space" => ch.is_whitespace(),
+        "punct" => ch.is_ascii_punctuation(),
+        "xdigit" => ch.is_ascii_hexdigit(),
+        _ => false
+    }
+}
+
+// matches the [...] at the start of pattern against ch, returns whether it matched
+// and how long the bracket expression is. None when the [ is never closed, it's
+// just a [ then
+fn match_bracket(pattern: &[char], ch: char) -> Option<(bool, usize)> {
+    let mut idx = 1;
+    let negated = matches!(pattern.get(idx), Some('!') | Some('^'));
+
+    if negated {
+        idx += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        let current = *pattern.get(idx)?;
+
+        // a ] right after the [ or [! is part of the set
+        if current == ']' && !first {
+            return Some((matched != negated, idx + 1));
+        }
+
+        first = false;
+
+        if current == '[' && pattern.get(idx + 1) == Some(&':') {
+            let rest: String = pattern[idx + 2..].iter().collect();
+
+            if let Some(end) = rest.find(":]") {
+                matched |= in_class(&rest[..end], ch);
+                idx += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let low = match current {
+            '\\' if idx + 1 < pattern.len() => {
+                idx += 1;
+                pattern[idx]
+            },
+            _ => current
+        };
+
+        idx += 1;
+
+        if pattern.get(idx) == Some(&'-') && pattern.get(idx + 1).is_some_and(|high| *high != ']') {
+            let high = pattern[idx + 1];
+            matched |= low <= ch && ch <= high;
+            idx += 2;
+        }
+
+        else {
+            matched |= low == ch;
+        }
+    }
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => {
+            let rest = &pattern[1..];
+
+            // a run of * matches the same as one
+            if rest.first() == Some(&'*') {
+                return match_from(rest, text);
+            }
+
+            (0..=text.len()).any(|skip| match_from(rest, &text[skip..]))
+        },
+        Some('?') => !text.is_empty() && match_from(&pattern[1..], &text[1..]),
+        Some('[') => {
+            let ch = match text.first() {
+                Some(ch) => *ch,
+                None => return false
+            };
+
+            match match_bracket(pattern, ch) {
+                Some((matched, len)) => matched && match_from(&pattern[len..], &text[1..]),
+                None => ch == '[' && match_from(&pattern[1..], &text[1..])
+            }
+        },
+        Some('\\') if pattern.len() > 1 => text.first() == Some(&pattern[1]) && match_from(&pattern[2..], &text[1..]),
+        Some(ch) => text.first() == Some(ch) && match_from(&pattern[1..], &text[1..])
+    }
+}
+
+// whether all of text matches pattern
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    match_from(&pattern, &text)
+}
+
+// text with the characters that mean something in a pattern escaped, so that it
+// only matches itself
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        if matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+
+        escaped.push(ch);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "abc"));
+        assert!(glob_match("a*c", "abbc"));
+        assert!(glob_match("a**c", "ac"));
+        assert!(!glob_match("a*c", "abcd"));
+        assert!(glob_match("?b?", "abc"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("??", "a"));
+        assert!(glob_match("é?", "éü"));
+    }
+
+    #[test]
+    fn brackets() {
+        assert!(glob_match("[abc]", "b"));
+        assert!(!glob_match("[abc]", "d"));
+        assert!(glob_match("[a-c]x", "bx"));
+        assert!(!glob_match("[a-c]", "d"));
+        assert!(glob_match("[!a-c]", "d"));
+        assert!(!glob_match("[!a-c]", "a"));
+        assert!(glob_match("[^a]", "b"));
+        assert!(!glob_match("[abc]", ""));
+    }
+
+    #[test]
+    fn bracket_edge_cases() {
+        // a ] first in the set is part of it
+        assert!(glob_match("[]a]", "]"));
+        assert!(glob_match("[!]]", "a"));
+        assert!(!glob_match("[!]]", "]"));
+
+        // a - at the end is literal
+        assert!(glob_match("[a-]", "-"));
+
+        // an unclosed [ is just a [
+        assert!(glob_match("[ab", "[ab"));
+        assert!(!glob_match("[ab", "a"));
+
+        assert!(glob_match("[\\]]", "]"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob_match("[[:alpha:]]", "q"));
+        assert!(!glob_match("[[:alpha:]]", "1"));
+        assert!(glob_match("[[:digit:]]*", "1abc"));
+        assert!(glob_match("[![:digit:]]", "a"));
+        assert!(glob_match("[[:upper:][:digit:]_]", "_"));
+        assert!(glob_match("[[:space:]]", "\t"));
+        assert!(!glob_match("[[:bogus:]]", "a"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(glob_match("\\*", "*"));
+        assert!(!glob_match("\\*", "a"));
+        assert!(glob_match("a\\?", "a?"));
+        assert!(glob_match("\\[a]", "[a]"));
+
+        // a trailing backslash matches itself
+        assert!(glob_match("a\\", "a\\"));
+    }
+
+    #[test]
+    fn escaped_text_only_matches_itself() {
+        for text in ["*", "a?b", "[x]", "back\\slash", "plain"] {
+            assert!(glob_match(&escape(text), text), "{:?}", text);
+        }
+
+        assert_eq!(escape("a*[b]?\\"), "a\\*\\[b\\]\\?\\\\");
+        assert!(!glob_match(&escape("a*"), "abc"));
+    }
+}
@@ -0,0 +1,103 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// byte offset of the grapheme cluster boundary before idx
+pub fn prev_boundary(line: &str, idx: usize) -> usize {
+    line[..idx].grapheme_indices(true).next_back().map(|(start, _)| start).unwrap_or(0)
+}
+
+// byte offset of the grapheme cluster boundary after idx
+pub fn next_boundary(line: &str, idx: usize) -> usize {
+    match line[idx..].graphemes(true).next() {
+        Some(grapheme) => idx + grapheme.len(),
+        None => line.len()
+    }
+}
+
+// columns a grapheme takes on the terminal, emoji sequences joined with ZWJ
+// are still drawn as a single double width glyph
+pub fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.width().min(2)
+}
+
+// text without the escape sequences that don't take up any columns, colours and
+// the like, plus anything between \x01 and \x02 like readline does
+pub fn strip_escapes(text: &str) -> String {
+    let mut visible = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\x1b' => match chars.next() {
+                // CSI, ends at the first byte in @..~
+                Some('[') => {
+                    for ch in chars.by_ref() {
+                        if ('@'..='~').contains(&ch) {
+                            break;
+                        }
+                    }
+                },
+                // OSC, ends with BEL or ESC \
+                Some(']') => {
+                    while let Some(ch) = chars.next() {
+                        if ch == '\x07' {
+                            break;
+                        }
+
+                        if ch == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                },
+                _ => {}
+            },
+            '\x01' => {
+                for ch in chars.by_ref() {
+                    if ch == '\x02' {
+                        break;
+                    }
+                }
+            },
+            _ => visible.push(ch)
+        }
+    }
+
+    visible
+}
+
+pub fn display_width(text: &str) -> usize {
+    strip_escapes(text).graphemes(true).map(grapheme_width).sum()
+}
+
+// row and column (both from 0) where whatever comes after text gets drawn, once
+// text has been written from the start of a row in a terminal cols wide
+pub fn position_after(text: &str, cols: usize) -> (usize, usize) {
+    let mut row = 0;
+    let mut col = 0;
+
+    for grapheme in strip_escapes(text).graphemes(true) {
+        if grapheme == "\n" || grapheme == "\r\n" {
+            row += 1;
+            col = 0;
+            continue;
+        }
+
+        let width = grapheme_width(grapheme);
+
+        // a wide character that doesn't fit is moved to the next row whole
+        if col + width > cols {
+            row += 1;
+            col = 0;
+        }
+
+        col += width;
+
+        if col >= cols {
+            row += 1;
+            col = 0;
+        }
+    }
+
+    (row, col)
+}
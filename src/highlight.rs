@@ -0,0 +1,147 @@
+use crate::completion;
+use crate::config;
+use crate::lexer::{self, TokenType};
+use crate::parser::{self, WordType};
+
+use termion::color;
+
+// characters that make a word more than plain text
+const SPECIAL: &[char] = &['\\', '\'', '"', '`', '$'];
+
+fn paint(highlighted: &mut String, text: &str, colour: &dyn color::Color) {
+    highlighted.push_str(format!("{}{}{}", color::Fg(colour), text, color::Fg(color::Reset)).as_str());
+}
+
+// length of the $VAR, $? or $1 at the start of text
+fn variable_len(text: &str) -> usize {
+    let rest = &text[1..];
+
+    if rest.starts_with(['?', '$', '#', '!', '@', '*']) || rest.starts_with(|ch: char| ch.is_ascii_digit()) {
+        return 2;
+    }
+
+    1 + rest.find(|ch: char| !ch.is_alphanumeric() && ch != '_').unwrap_or(rest.len())
+}
+
+fn command_colour(cfg: &config::Config, word: &str) -> &'static dyn color::Color {
+    match parser::classify_word(cfg, word) {
+        WordType::Builtin | WordType::Function | WordType::Keyword => &color::Green,
+        WordType::General if completion::find_command(cfg, word) || cfg.abbreviations.contains_key(word) => &color::Green,
+        _ => &color::Red
+    }
+}
+
+// colours the quoted parts and expansions of a word, what's inside $(...) is
+// highlighted like a command line of its own
+fn highlight_word(cfg: &config::Config, highlighted: &mut String, word: &str) {
+    let mut plain_start = 0;
+    let mut pos = 0;
+
+    while let Some(ch) = word[pos..].chars().next() {
+        if ch == '\\' {
+            pos += 1 + word[pos + 1..].chars().next().map_or(0, |next| next.len_utf8());
+            continue;
+        }
+
+        if !SPECIAL.contains(&ch) {
+            pos += ch.len_utf8();
+            continue;
+        }
+
+        highlighted.push_str(&word[plain_start..pos]);
+        let end = lexer::part_end(word, pos);
+
+        if word[pos..].starts_with("$(") {
+            let inner = &word[pos + 2..end];
+
+            paint(highlighted, "$(", &color::Blue);
+
+            match inner.strip_suffix(')') {
+                Some(inner) => {
+                    highlighted.push_str(&highlight(cfg, inner));
+                    paint(highlighted, ")", &color::Blue);
+                },
+                None => highlighted.push_str(&highlight(cfg, inner))
+            }
+
+            pos = end;
+        }
+
+        else if word[pos..].starts_with("${") {
+            paint(highlighted, &word[pos..end], &color::Cyan);
+            pos = end;
+        }
+
+        else if ch == '$' && !word[pos..].starts_with("$'") {
+            let len = variable_len(&word[pos..]);
+            paint(highlighted, &word[pos..pos + len], &color::Cyan);
+            pos += len;
+        }
+
+        else {
+            paint(highlighted, &word[pos..end], &color::Yellow);
+            pos = end;
+        }
+
+        plain_start = pos;
+    }
+
+    highlighted.push_str(&word[plain_start..]);
+}
+
+// the line with colour escapes added, it's split into tokens the same way the
+// parser does it when the line runs
+pub fn highlight(cfg: &config::Config, line: &str) -> String {
+    let mut highlighted = String::with_capacity(line.len() * 2);
+    let mut last_end = 0;
+    let mut command_position = true;
+    let mut redirect_target = false;
+
+    for token in lexer::tokenize_partial(line) {
+        // blanks and comments between tokens are left as they are
+        highlighted.push_str(&line[last_end..token.start]);
+        last_end = token.end;
+
+        match token.t_type {
+            TokenType::Word if redirect_target => {
+                highlight_word(cfg, &mut highlighted, &token.text);
+                redirect_target = false;
+            },
+            TokenType::Word if command_position && token.text == "!" => {
+                paint(&mut highlighted, &token.text, &color::Blue);
+            },
+            TokenType::Word if command_position && parser::is_assignment(&token.text) => {
+                highlight_word(cfg, &mut highlighted, &token.text);
+            },
+            TokenType::Word if command_position => {
+                if token.text.contains(SPECIAL) {
+                    highlight_word(cfg, &mut highlighted, &token.text);
+                }
+
+                else {
+                    paint(&mut highlighted, &token.text, command_colour(cfg, &token.text));
+                }
+
+                // after a keyword like `if` or `then` comes another command
+                command_position = cfg.keywords.contains_key(&token.text);
+            },
+            TokenType::Word => highlight_word(cfg, &mut highlighted, &token.text),
+            TokenType::IoNumber => paint(&mut highlighted, &token.text, &color::Magenta),
+            t_type if lexer::is_redirection(t_type) => {
+                paint(&mut highlighted, &token.text, &color::Magenta);
+                redirect_target = true;
+            },
+            TokenType::RParen => {
+                paint(&mut highlighted, &token.text, &color::Blue);
+                command_position = false;
+            },
+            _ => {
+                paint(&mut highlighted, &token.text, &color::Blue);
+                command_position = true;
+            }
+        }
+    }
+
+    highlighted.push_str(&line[last_end..]);
+    highlighted
+}
@@ -0,0 +1,276 @@
+use crate::config;
+use crate::log;
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+const DEFAULT_HISTSIZE: usize = 1000;
+const DEFAULT_HISTFILE: &str = ".rsh_history";
+
+// written on its own line before an entry, followed by the status and the directory
+// the command ran in, files without them still load fine but only with one line
+// per entry
+const META_PREFIX: &str = "#rsh:";
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub line: String,
+    pub cwd: String,
+
+    // None until the command finishes, or when the file didn't say
+    pub status: Option<i32>,
+}
+
+pub fn history_file(cfg: &config::Config) -> Option<PathBuf> {
+    if let Some(path) = cfg.variables.get("HISTFILE") {
+        if path.is_empty() {
+            return None;
+        }
+
+        return Some(PathBuf::from(path));
+    }
+
+    cfg.variables.get("HOME").map(|home| PathBuf::from(home).join(DEFAULT_HISTFILE))
+}
+
+pub fn history_size(cfg: &config::Config) -> usize {
+    match cfg.variables.get("HISTSIZE") {
+        Some(size) => size.parse::<usize>().unwrap_or(DEFAULT_HISTSIZE),
+        None => DEFAULT_HISTSIZE
+    }
+}
+
+fn format_entry(entry: &HistoryEntry) -> String {
+    let status = entry.status.map(|status| status.to_string()).unwrap_or_default();
+    format!("{}{}:{}\n{}\n", META_PREFIX, status, entry.cwd, entry.line)
+}
+
+fn parse_entries(contents: &str) -> Vec<HistoryEntry> {
+    let mut entries: Vec<HistoryEntry> = Vec::new();
+    let mut meta: Option<(Option<i32>, String)> = None;
+
+    // set while reading an entry that came after a meta line, everything up to the
+    // next meta line belongs to it so that multi-line commands stay one entry
+    let mut in_entry = false;
+
+    for line in contents.lines() {
+        if let Some(rest) = line.strip_prefix(META_PREFIX) {
+            if let Some((status, cwd)) = rest.split_once(':') {
+                meta = Some((status.parse::<i32>().ok(), cwd.to_string()));
+                in_entry = false;
+                continue;
+            }
+        }
+
+        if in_entry {
+            let entry = entries.last_mut().unwrap();
+            entry.line.push('\n');
+            entry.line.push_str(line);
+            continue;
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+
+        in_entry = meta.is_some();
+
+        let (status, cwd) = meta.take().unwrap_or((None, String::new()));
+        entries.push(HistoryEntry { line: line.to_string(), cwd, status });
+    }
+
+    entries
+}
+
+pub fn load_history(cfg: &mut config::Config) {
+    cfg.history.clear();
+
+    let path = match history_file(cfg) {
+        Some(path) => path,
+        None => return
+    };
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            log::debug(cfg, format!("could not read history file {:?}: {}", path, e).as_str());
+            return;
+        }
+    };
+
+    let entries = parse_entries(&contents);
+
+    let max_size = history_size(cfg);
+    let skip = entries.len().saturating_sub(max_size);
+
+    cfg.history.extend(entries.into_iter().skip(skip));
+
+    // keep the file from growing forever, entries are appended on every command
+    if skip > 0 {
+        let truncated: String = cfg.history.iter().map(format_entry).collect();
+
+        let result = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| file.write_all(truncated.as_bytes()));
+
+        if let Err(e) = result {
+            log::debug(cfg, format!("could not truncate history file {:?}: {}", path, e).as_str());
+        }
+    }
+}
+
+// adds line before it runs, finish_entry records how it went once it's done
+pub fn add_entry(cfg: &mut config::Config, line: &str) {
+    if line.is_empty() || cfg.history.last().map(|last| last.line == line).unwrap_or(false) {
+        return;
+    }
+
+    let cwd = env::current_dir().map(|dir| dir.to_string_lossy().to_string()).unwrap_or_default();
+    cfg.history.push(HistoryEntry { line: line.to_string(), cwd, status: None });
+
+    let max_size = history_size(cfg);
+    if cfg.history.len() > max_size {
+        let excess = cfg.history.len() - max_size;
+        cfg.history.drain(..excess);
+    }
+}
+
+// sets the status of the latest run of line and saves it to the history file, entries
+// are only written once they finish so the status can go along with them
+pub fn finish_entry(cfg: &mut config::Config, line: &str, status: i32) {
+    let entry = match cfg.history.iter_mut().rev().find(|entry| entry.line == line) {
+        Some(entry) => entry,
+        None => return
+    };
+
+    // a repeated command only updates the status of the entry that was already saved
+    let pending = entry.status.is_none();
+    entry.status = Some(status);
+
+    if !pending {
+        return;
+    }
+
+    let formatted = format_entry(entry);
+
+    let path = match history_file(cfg) {
+        Some(path) => path,
+        None => return
+    };
+
+    // what was typed can have passwords and the like in it, only the user gets to read it
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(&path);
+
+    match file {
+        Ok(mut file) => {
+            if let Err(e) = file.write_all(formatted.as_bytes()) {
+                log::debug(cfg, format!("could not write to history file {:?}: {}", path, e).as_str());
+            }
+        },
+        Err(e) => log::debug(cfg, format!("could not open history file {:?}: {}", path, e).as_str())
+    }
+}
+
+// index of the closest entry containing query, starting at `from` and walking
+// towards older entries when reverse is set, newer ones otherwise
+pub fn search(cfg: &config::Config, query: &str, from: usize, reverse: bool) -> Option<usize> {
+    if cfg.history.is_empty() {
+        return None;
+    }
+
+    if reverse {
+        let from = from.min(cfg.history.len() - 1);
+        (0..=from).rev().find(|&idx| cfg.history[idx].line.contains(query))
+    }
+
+    else {
+        (from..cfg.history.len()).find(|&idx| cfg.history[idx].line.contains(query))
+    }
+}
+
+// an entry the way fc refers to it: a positive number counts from the first entry,
+// a negative one back from the last and anything else is the prefix of an entry
+pub fn find_entry(entries: &[String], spec: &str) -> Option<usize> {
+    match spec.parse::<i64>() {
+        Ok(num) if num > 0 && (num as usize) <= entries.len() => Some(num as usize - 1),
+        Ok(num) if num < 0 => entries.len().checked_sub(num.unsigned_abs() as usize),
+        Ok(_) => None,
+        Err(_) => entries.iter().rposition(|entry| entry.starts_with(spec))
+    }
+}
+
+// what's left of the entry that should be suggested for what's been typed so far,
+// entries from the current directory come first, then ones that didn't fail, and
+// the most recent one among those
+pub fn suggest(cfg: &config::Config, typed: &str) -> Option<String> {
+    if typed.trim().is_empty() {
+        return None;
+    }
+
+    let cwd = env::current_dir().map(|dir| dir.to_string_lossy().to_string()).unwrap_or_default();
+
+    cfg.history.iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.line.len() > typed.len() && entry.line.starts_with(typed))
+        .max_by_key(|(idx, entry)| (entry.cwd == cwd, entry.status.is_none_or(|status| status == 0), *idx))
+        .map(|(_, entry)| entry.line[typed.len()..].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(line: &str, cwd: &str, status: Option<i32>) -> HistoryEntry {
+        HistoryEntry { line: line.to_string(), cwd: cwd.to_string(), status }
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries = vec![
+            entry("echo one", "/home/user", Some(0)),
+            entry("for x in a b\ndo\n  echo $x\n\ndone", "/tmp/with:colon", Some(130)),
+            entry("#rsh is not a meta line without a colon", "", None),
+        ];
+
+        let formatted: String = entries.iter().map(format_entry).collect();
+        let parsed = parse_entries(&formatted);
+
+        assert_eq!(parsed.len(), entries.len());
+
+        for (parsed, entry) in parsed.iter().zip(&entries) {
+            assert_eq!(parsed.line, entry.line);
+            assert_eq!(parsed.cwd, entry.cwd);
+            assert_eq!(parsed.status, entry.status);
+        }
+    }
+
+    #[test]
+    fn plain_files() {
+        // one entry per line and no status or directory, like bash writes it
+        let parsed = parse_entries("ls -l\n\ncd /tmp\n");
+
+        assert_eq!(parsed.iter().map(|entry| entry.line.as_str()).collect::<Vec<_>>(), vec!["ls -l", "cd /tmp"]);
+        assert!(parsed.iter().all(|entry| entry.status.is_none() && entry.cwd.is_empty()));
+    }
+
+    #[test]
+    fn mixed_files() {
+        let parsed = parse_entries("old\n#rsh:1:/srv\nfalse\n#rsh::/\nunfinished\n");
+
+        assert_eq!(parsed.len(), 3);
+        assert_eq!((parsed[0].line.as_str(), parsed[0].status), ("old", None));
+        assert_eq!((parsed[1].line.as_str(), parsed[1].status, parsed[1].cwd.as_str()), ("false", Some(1), "/srv"));
+        assert_eq!((parsed[2].line.as_str(), parsed[2].status, parsed[2].cwd.as_str()), ("unfinished", None, "/"));
+    }
+}
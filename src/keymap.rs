@@ -0,0 +1,430 @@
+use crate::config;
+use crate::log;
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use termion::event::{self, Event, Key};
+
+// every editor action a key sequence can be bound to
+pub const ACTIONS: &[&str] = &[
+    "accept-line",
+    "backward-char",
+    "backward-delete-char",
+    "backward-word",
+    "beginning-of-line",
+    "clear-screen",
+    "complete",
+    "delete-char",
+    "edit-and-execute",
+    "edit-and-execute-command",
+    "edit-command-line",
+    "end-of-line",
+    "forward-char",
+    "forward-search-history",
+    "forward-word",
+    "history-search-backward",
+    "history-search-forward",
+    "kill-line",
+    "kill-word",
+    "next-history",
+    "previous-history",
+    "redo",
+    "reverse-search-history",
+    "self-insert",
+    "transpose-chars",
+    "undo",
+    "unix-line-discard",
+    "unix-word-rubout",
+    "vi-movement-mode",
+    "yank",
+    "yank-pop",
+];
+
+pub enum Lookup {
+    Action(String),
+    // the keys so far start a longer sequence, more have to be read
+    Prefix,
+    Unbound
+}
+
+pub fn load_default_keymap(cfg: &mut config::Config) {
+    let defaults = [
+        (Key::Char('\n'), "accept-line"),
+        (Key::Char('\t'), "complete"),
+        (Key::Backspace, "backward-delete-char"),
+        (Key::Delete, "delete-char"),
+        (Key::Left, "backward-char"),
+        (Key::Right, "forward-char"),
+        (Key::Up, "previous-history"),
+        (Key::Down, "next-history"),
+        (Key::Home, "beginning-of-line"),
+        (Key::End, "end-of-line"),
+        (Key::Esc, "vi-movement-mode"),
+        (Key::Alt('b'), "backward-word"),
+        (Key::Alt('f'), "forward-word"),
+        (Key::Alt('d'), "kill-word"),
+        (Key::Alt('y'), "yank-pop"),
+        (Key::Alt('/'), "redo"),
+        (Key::Ctrl('a'), "beginning-of-line"),
+        (Key::Ctrl('e'), "end-of-line"),
+        (Key::Ctrl('b'), "backward-char"),
+        (Key::Ctrl('f'), "forward-char"),
+        (Key::Ctrl('d'), "delete-char"),
+        (Key::Ctrl('w'), "unix-word-rubout"),
+        (Key::Ctrl('k'), "kill-line"),
+        (Key::Ctrl('u'), "unix-line-discard"),
+        (Key::Ctrl('y'), "yank"),
+        (Key::Ctrl('t'), "transpose-chars"),
+        (Key::Ctrl('l'), "clear-screen"),
+        (Key::Ctrl('r'), "reverse-search-history"),
+        (Key::Ctrl('s'), "forward-search-history"),
+        (Key::Ctrl('7'), "undo"),
+    ];
+
+    for (key, action) in defaults {
+        cfg.keymap.insert(vec![key], action.to_string());
+    }
+
+    cfg.keymap.insert(vec![Key::Ctrl('x'), Key::Ctrl('e')], String::from("edit-and-execute-command"));
+    cfg.keymap.insert(vec![Key::Ctrl('x'), Key::Ctrl('u')], String::from("undo"));
+}
+
+pub fn lookup(cfg: &config::Config, keys: &[Key]) -> Lookup {
+    if let Some(action) = cfg.keymap.get(keys) {
+        return Lookup::Action(action.clone());
+    }
+
+    if cfg.keymap.keys().any(|seq| seq.len() > keys.len() && seq.starts_with(keys)) {
+        return Lookup::Prefix;
+    }
+
+    Lookup::Unbound
+}
+
+// turns the raw bytes a terminal would send into keys, the same way they're read
+fn parse_bytes(bytes: &[u8]) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut iter = bytes.iter().map(|byte| Ok::<u8, io::Error>(*byte));
+
+    while let Some(Ok(byte)) = iter.next() {
+        let mut rest = iter.clone().peekable();
+
+        // a lone escape is the escape key, not the start of a sequence
+        if byte == b'\x1b' && rest.peek().is_none() {
+            keys.push(Key::Esc);
+            break;
+        }
+
+        match event::parse_event(byte, &mut iter) {
+            Ok(Event::Key(key)) => keys.push(key),
+            _ => return Err(format!("unsupported key sequence: {:?}", String::from_utf8_lossy(bytes)))
+        }
+    }
+
+    Ok(keys)
+}
+
+fn control_byte(ch: char) -> u8 {
+    if ch == '?' {
+        0x7f
+    }
+
+    else {
+        ch.to_ascii_lowercase() as u8 & 0x1f
+    }
+}
+
+// the inputrc notation inside double quotes: \C-x, \M-x, \e and the usual escapes
+pub fn parse_key_sequence(text: &str) -> Result<Vec<Key>, String> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('C') if chars.peek() == Some(&'-') => {
+                chars.next();
+
+                match chars.next() {
+                    Some(ch) => bytes.push(control_byte(ch)),
+                    None => return Err(String::from("missing key after \\C-"))
+                }
+            },
+            Some('M') if chars.peek() == Some(&'-') => {
+                chars.next();
+                bytes.push(b'\x1b');
+
+                // \M-\C-x is also allowed
+                if chars.peek() == Some(&'\\') {
+                    continue;
+                }
+
+                match chars.next() {
+                    Some(ch) => bytes.push(ch as u8),
+                    None => return Err(String::from("missing key after \\M-"))
+                }
+            },
+            Some('e') => bytes.push(b'\x1b'),
+            Some('t') => bytes.push(b'\t'),
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('d') => bytes.push(0x7f),
+            Some(ch) => bytes.push(ch as u8),
+            None => return Err(String::from("trailing backslash in key sequence"))
+        }
+    }
+
+    parse_bytes(&bytes)
+}
+
+// the inputrc notation without quotes: Control-u, Meta-f, Tab, Up...
+fn parse_key_name(name: &str) -> Result<Vec<Key>, String> {
+    let lower = name.to_ascii_lowercase();
+
+    let key = match lower.as_str() {
+        "tab" => Key::Char('\t'),
+        "return" | "ret" | "newline" | "enter" => Key::Char('\n'),
+        "esc" | "escape" => Key::Esc,
+        "space" | "spc" => Key::Char(' '),
+        "rubout" | "del" | "backspace" => Key::Backspace,
+        "delete" => Key::Delete,
+        "insert" => Key::Insert,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        _ => {
+            if let Some(rest) = lower.strip_prefix("control-").or(lower.strip_prefix("c-")) {
+                return parse_key_sequence(format!("\\C-{}", rest).as_str());
+            }
+
+            if let Some(rest) = name.strip_prefix("Meta-").or(name.strip_prefix("M-")) {
+                return parse_key_sequence(format!("\\M-{}", rest).as_str());
+            }
+
+            let mut chars = name.chars();
+
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => Key::Char(ch),
+                _ => return Err(format!("unknown key name: {}", name))
+            }
+        }
+    };
+
+    Ok(vec![key])
+}
+
+// parses one `"keyseq": action` or `keyname: action` binding
+pub fn parse_binding(line: &str) -> Result<(Vec<Key>, String), String> {
+    let line = line.trim();
+
+    let (keys, rest) = if let Some(quoted) = line.strip_prefix('"') {
+        let mut end = None;
+        let mut escaped = false;
+
+        for (idx, ch) in quoted.char_indices() {
+            if escaped {
+                escaped = false;
+            }
+
+            else if ch == '\\' {
+                escaped = true;
+            }
+
+            else if ch == '"' {
+                end = Some(idx);
+                break;
+            }
+        }
+
+        let end = match end {
+            Some(end) => end,
+            None => return Err(String::from("missing closing quote"))
+        };
+
+        (parse_key_sequence(&quoted[..end])?, &quoted[end + 1..])
+    }
+
+    else {
+        match line.find(':') {
+            Some(idx) => (parse_key_name(line[..idx].trim())?, &line[idx..]),
+            None => return Err(String::from("missing colon"))
+        }
+    };
+
+    let action = match rest.trim_start().strip_prefix(':') {
+        Some(action) => action.trim(),
+        None => return Err(String::from("missing colon"))
+    };
+
+    if keys.is_empty() {
+        return Err(String::from("empty key sequence"));
+    }
+
+    if !ACTIONS.contains(&action) {
+        return Err(format!("{}: unknown function name", action));
+    }
+
+    Ok((keys, action.to_string()))
+}
+
+fn format_key(key: &Key) -> String {
+    match key {
+        Key::Char('\n') => String::from("\\n"),
+        Key::Char('\t') => String::from("\\t"),
+        Key::Char('\\') => String::from("\\\\"),
+        Key::Char('"') => String::from("\\\""),
+        Key::Char(ch) => ch.to_string(),
+        // termion reports the control characters after Ctrl-Z as digits
+        Key::Ctrl('5') => String::from("\\C-]"),
+        Key::Ctrl('6') => String::from("\\C-^"),
+        Key::Ctrl('7') => String::from("\\C-_"),
+        Key::Ctrl(ch) => format!("\\C-{}", ch),
+        Key::Alt(ch) => format!("\\e{}", ch),
+        Key::Null => String::from("\\C-@"),
+        Key::Esc => String::from("\\e"),
+        Key::Backspace => String::from("\\C-?"),
+        Key::Up => String::from("\\e[A"),
+        Key::Down => String::from("\\e[B"),
+        Key::Right => String::from("\\e[C"),
+        Key::Left => String::from("\\e[D"),
+        Key::Home => String::from("\\e[H"),
+        Key::End => String::from("\\e[F"),
+        Key::BackTab => String::from("\\e[Z"),
+        Key::Insert => String::from("\\e[2~"),
+        Key::Delete => String::from("\\e[3~"),
+        Key::PageUp => String::from("\\e[5~"),
+        Key::PageDown => String::from("\\e[6~"),
+        Key::F(n) => format!("<F{}>", n),
+        _ => String::new()
+    }
+}
+
+pub fn format_key_sequence(keys: &[Key]) -> String {
+    keys.iter().map(format_key).collect()
+}
+
+pub fn load_inputrc_file(cfg: &mut config::Config, path: &PathBuf) -> Result<(), String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => return Err(format!("{}: {}", path.display(), e))
+    };
+
+    for (line_num, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        // conditionals ($if, $endif...) aren't supported, what's inside them always applies
+        if line.is_empty() || line.starts_with('#') || line.starts_with('$') {
+            continue;
+        }
+
+        if let Some(setting) = line.strip_prefix("set ") {
+            let mut parts = setting.split_whitespace();
+
+            if let (Some("editing-mode"), Some(mode)) = (parts.next(), parts.next()) {
+                if mode == "vi" || mode == "emacs" {
+                    cfg.options.insert(String::from("vi"), mode == "vi");
+                    cfg.options.insert(String::from("emacs"), mode == "emacs");
+                }
+            }
+
+            continue;
+        }
+
+        match parse_binding(line) {
+            Ok((keys, action)) => {
+                cfg.keymap.insert(keys, action);
+            },
+            Err(e) => eprintln!("rsh: {}: line {}: {}", path.display(), line_num + 1, e)
+        }
+    }
+
+    Ok(())
+}
+
+pub fn load_inputrc(cfg: &mut config::Config) {
+    // not $INPUTRC, that's readline's file and full of functions rsh doesn't have
+    let path = match cfg.variables.get("HOME") {
+        Some(home) => PathBuf::from(home).join(".rshinputrc"),
+        None => return
+    };
+
+    if !path.exists() {
+        return;
+    }
+
+    if let Err(e) = load_inputrc_file(cfg, &path) {
+        log::debug(cfg, format!("could not load inputrc: {}", e).as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_sequences() {
+        assert_eq!(parse_key_sequence("\\C-x\\C-e").unwrap(), vec![Key::Ctrl('x'), Key::Ctrl('e')]);
+        assert_eq!(parse_key_sequence("\\C-X").unwrap(), vec![Key::Ctrl('x')]);
+        assert_eq!(parse_key_sequence("\\M-f").unwrap(), vec![Key::Alt('f')]);
+        assert_eq!(parse_key_sequence("\\ef").unwrap(), vec![Key::Alt('f')]);
+        assert_eq!(parse_key_sequence("\\e[A").unwrap(), vec![Key::Up]);
+        assert_eq!(parse_key_sequence("\\e[3~").unwrap(), vec![Key::Delete]);
+        assert_eq!(parse_key_sequence("\\e").unwrap(), vec![Key::Esc]);
+        assert_eq!(parse_key_sequence("\\C-?").unwrap(), vec![Key::Backspace]);
+        assert_eq!(parse_key_sequence("ab\\t").unwrap(), vec![Key::Char('a'), Key::Char('b'), Key::Char('\t')]);
+    }
+
+    #[test]
+    fn bad_key_sequences() {
+        assert!(parse_key_sequence("\\C-").is_err());
+        assert!(parse_key_sequence("\\M-").is_err());
+        assert!(parse_key_sequence("a\\").is_err());
+    }
+
+    #[test]
+    fn bindings() {
+        assert_eq!(parse_binding("Control-u: kill-line").unwrap(), (vec![Key::Ctrl('u')], String::from("kill-line")));
+        assert_eq!(parse_binding("  Meta-f :forward-word  ").unwrap(), (vec![Key::Alt('f')], String::from("forward-word")));
+        assert_eq!(parse_binding("Up: history-search-backward").unwrap(), (vec![Key::Up], String::from("history-search-backward")));
+        assert_eq!(parse_binding("\"\\C-x\\C-e\": edit-and-execute").unwrap(), (vec![Key::Ctrl('x'), Key::Ctrl('e')], String::from("edit-and-execute")));
+
+        // a colon and a quote inside the quoted sequence are part of it
+        assert_eq!(parse_binding("\":\\\"\": undo").unwrap(), (vec![Key::Char(':'), Key::Char('"')], String::from("undo")));
+    }
+
+    #[test]
+    fn bad_bindings() {
+        assert_eq!(parse_binding("Control-u: no-such-action").unwrap_err(), "no-such-action: unknown function name");
+        assert_eq!(parse_binding("\"\\C-a: beginning-of-line").unwrap_err(), "missing closing quote");
+        assert_eq!(parse_binding("Control-u kill-line").unwrap_err(), "missing colon");
+        assert_eq!(parse_binding("\"\": undo").unwrap_err(), "empty key sequence");
+        assert_eq!(parse_binding("Hyper-x: undo").unwrap_err(), "unknown key name: Hyper-x");
+    }
+
+    #[test]
+    fn formatted_sequences_parse_back() {
+        let sequences = [
+            vec![Key::Ctrl('x'), Key::Ctrl('e')],
+            vec![Key::Alt('f')],
+            vec![Key::Up, Key::Down, Key::Left, Key::Right],
+            vec![Key::Home, Key::End, Key::Delete, Key::PageUp],
+            vec![Key::Char('"'), Key::Char('\\'), Key::Char('\t'), Key::Char('é')],
+            vec![Key::Backspace, Key::Ctrl('7')],
+        ];
+
+        for keys in sequences {
+            assert_eq!(parse_key_sequence(&format_key_sequence(&keys)).unwrap(), keys);
+        }
+    }
+}
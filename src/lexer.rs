@@ -0,0 +1,429 @@
+// splits a command line into words and operators. words are kept the way they were
+// typed, quotes included, expanding them is left for when they run
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenType {
+    Word,
+    // the digits in front of a redirection, like the 2 in 2>file
+    IoNumber,
+    Newline,
+    Pipe,
+    AndIf,
+    OrIf,
+    Semi,
+    DSemi,
+    Amp,
+    LParen,
+    RParen,
+    Less,
+    Great,
+    DGreat,
+    LessAnd,
+    GreatAnd,
+    LessGreat,
+    Clobber,
+    DLess,
+    DLessDash,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub t_type: TokenType,
+    pub text: String,
+
+    // byte offsets of the token in the line
+    pub start: usize,
+    pub end: usize,
+}
+
+// longest first, so that >> isn't read as two >
+const OPERATORS: &[(&str, TokenType)] = &[
+    ("<<-", TokenType::DLessDash),
+    ("&&", TokenType::AndIf),
+    ("||", TokenType::OrIf),
+    (";;", TokenType::DSemi),
+    ("<<", TokenType::DLess),
+    (">>", TokenType::DGreat),
+    ("<&", TokenType::LessAnd),
+    (">&", TokenType::GreatAnd),
+    ("<>", TokenType::LessGreat),
+    (">|", TokenType::Clobber),
+    ("|", TokenType::Pipe),
+    ("&", TokenType::Amp),
+    (";", TokenType::Semi),
+    ("(", TokenType::LParen),
+    (")", TokenType::RParen),
+    ("<", TokenType::Less),
+    (">", TokenType::Great),
+];
+
+// characters that end a word unless they're quoted
+const METACHARACTERS: &[char] = &[' ', '\t', '\n', '|', '&', ';', '<', '>', '(', ')'];
+
+pub fn is_redirection(t_type: TokenType) -> bool {
+    matches!(t_type, TokenType::Less | TokenType::Great | TokenType::DGreat | TokenType::LessAnd | TokenType::GreatAnd | TokenType::LessGreat | TokenType::Clobber | TokenType::DLess | TokenType::DLessDash)
+}
+
+struct Scanner<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        self.input[self.pos..].starts_with(text)
+    }
+
+    // the rest of '...', after the opening quote
+    fn skip_single_quoted(&mut self) -> Result<(), String> {
+        loop {
+            match self.bump() {
+                Some('\'') => return Ok(()),
+                Some(_) => {},
+                None => return Err(String::from("unexpected end of input while looking for matching `''"))
+            }
+        }
+    }
+
+    // the rest of "...", after the opening quote
+    fn skip_double_quoted(&mut self) -> Result<(), String> {
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(()),
+                Some('\\') => {
+                    self.bump();
+                },
+                // $'...' is just a $ and a quote between double quotes
+                Some('$') if self.peek() != Some('\'') => self.skip_dollar()?,
+                Some('`') => self.skip_backquoted()?,
+                Some(_) => {},
+                None => return Err(String::from("unexpected end of input while looking for matching `\"'"))
+            }
+        }
+    }
+
+    // the rest of `...`, after the opening backquote
+    fn skip_backquoted(&mut self) -> Result<(), String> {
+        loop {
+            match self.bump() {
+                Some('`') => return Ok(()),
+                Some('\\') => {
+                    self.bump();
+                },
+                Some(_) => {},
+                None => return Err(String::from("unexpected end of input while looking for matching ``'"))
+            }
+        }
+    }
+
+    // whatever follows a $, only the forms that can contain metacharacters matter here
+    fn skip_dollar(&mut self) -> Result<(), String> {
+        match self.peek() {
+            Some('(') => {
+                self.bump();
+                self.skip_parenthesized()
+            },
+            Some('{') => {
+                self.bump();
+                self.skip_braced()
+            },
+            Some('\'') => {
+                self.bump();
+                self.skip_ansi_c_quoted()
+            },
+            _ => Ok(())
+        }
+    }
+
+    // the rest of $(...) or $((...)), after the opening parenthesis
+    fn skip_parenthesized(&mut self) -> Result<(), String> {
+        let mut depth = 1;
+
+        loop {
+            match self.bump() {
+                Some('(') => depth += 1,
+                Some(')') => {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                },
+                Some('\\') => {
+                    self.bump();
+                },
+                Some('\'') => self.skip_single_quoted()?,
+                Some('"') => self.skip_double_quoted()?,
+                Some('`') => self.skip_backquoted()?,
+                Some('$') => self.skip_dollar()?,
+                Some(_) => {},
+                None => return Err(String::from("unexpected end of input while looking for matching `)'"))
+            }
+        }
+    }
+
+    // the rest of ${...}, after the opening brace
+    fn skip_braced(&mut self) -> Result<(), String> {
+        loop {
+            match self.bump() {
+                Some('}') => return Ok(()),
+                Some('\\') => {
+                    self.bump();
+                },
+                Some('\'') => self.skip_single_quoted()?,
+                Some('"') => self.skip_double_quoted()?,
+                Some('`') => self.skip_backquoted()?,
+                Some('$') => self.skip_dollar()?,
+                Some(_) => {},
+                None => return Err(String::from("unexpected end of input while looking for matching `}'"))
+            }
+        }
+    }
+
+    // the rest of $'...', after the opening quote, unlike '...' it has escapes
+    fn skip_ansi_c_quoted(&mut self) -> Result<(), String> {
+        loop {
+            match self.bump() {
+                Some('\'') => return Ok(()),
+                Some('\\') => {
+                    self.bump();
+                },
+                Some(_) => {},
+                None => return Err(String::from("unexpected end of input while looking for matching `''"))
+            }
+        }
+    }
+
+    // errors come with the offset of the quote or $ that was left open
+    fn skip_word(&mut self) -> Result<(), (usize, String)> {
+        while let Some(ch) = self.peek() {
+            if METACHARACTERS.contains(&ch) {
+                break;
+            }
+
+            let start = self.pos;
+            self.bump();
+
+            let result = match ch {
+                '\\' => match self.bump() {
+                    Some(_) => Ok(()),
+                    None => Err(String::from("unexpected end of input after `\\'"))
+                },
+                '\'' => self.skip_single_quoted(),
+                '"' => self.skip_double_quoted(),
+                '`' => self.skip_backquoted(),
+                '$' => self.skip_dollar(),
+                _ => Ok(())
+            };
+
+            result.map_err(|e| (start, e))?;
+        }
+
+        Ok(())
+    }
+
+    // blanks and line continuations between tokens
+    fn skip_blanks(&mut self) {
+        loop {
+            if self.starts_with("\\\n") {
+                self.pos += 2;
+            }
+
+            else if self.peek() == Some(' ') || self.peek() == Some('\t') {
+                self.bump();
+            }
+
+            else {
+                break;
+            }
+        }
+    }
+}
+
+// with partial set, a quote or $( that isn't closed makes the rest of the input one
+// last word instead of an error, the way the line editor needs it while typing
+fn lex(input: &str, partial: bool) -> Result<Vec<Token>, (usize, String)> {
+    let mut scanner = Scanner { input, pos: 0 };
+    let mut tokens: Vec<Token> = Vec::new();
+
+    loop {
+        scanner.skip_blanks();
+
+        let start = scanner.pos;
+
+        let ch = match scanner.peek() {
+            Some(ch) => ch,
+            None => break
+        };
+
+        if ch == '#' {
+            while scanner.peek().is_some_and(|ch| ch != '\n') {
+                scanner.bump();
+            }
+
+            continue;
+        }
+
+        if ch == '\n' {
+            scanner.bump();
+            tokens.push(Token { t_type: TokenType::Newline, text: String::from("\n"), start, end: scanner.pos });
+            continue;
+        }
+
+        if let Some((operator, t_type)) = OPERATORS.iter().find(|(operator, _)| scanner.starts_with(operator)) {
+            scanner.pos += operator.len();
+            tokens.push(Token { t_type: *t_type, text: operator.to_string(), start, end: scanner.pos });
+            continue;
+        }
+
+        let result = scanner.skip_word();
+
+        if let Err(e) = result {
+            if !partial {
+                return Err(e);
+            }
+
+            scanner.pos = input.len();
+        }
+
+        let text = &input[start..scanner.pos];
+
+        // digits right before < or > say which fd is redirected
+        let t_type = if text.chars().all(|ch| ch.is_ascii_digit()) && (scanner.starts_with("<") || scanner.starts_with(">")) {
+            TokenType::IoNumber
+        }
+
+        else {
+            TokenType::Word
+        };
+
+        tokens.push(Token { t_type, text: text.to_string(), start, end: scanner.pos });
+    }
+
+    Ok(tokens)
+}
+
+// an error comes with the offset in input where it is
+pub fn tokenize(input: &str) -> Result<Vec<Token>, (usize, String)> {
+    lex(input, false)
+}
+
+pub fn tokenize_partial(input: &str) -> Vec<Token> {
+    lex(input, true).unwrap_or_default()
+}
+
+// end of the quoted part, $(...), ${...} or `...` that starts at pos in a word, or
+// the end of the word when it isn't closed
+pub fn part_end(word: &str, pos: usize) -> usize {
+    let mut scanner = Scanner { input: word, pos };
+
+    let result = match scanner.bump() {
+        Some('\'') => scanner.skip_single_quoted(),
+        Some('"') => scanner.skip_double_quoted(),
+        Some('`') => scanner.skip_backquoted(),
+        Some('$') => scanner.skip_dollar(),
+        _ => Ok(())
+    };
+
+    match result {
+        Ok(()) => scanner.pos,
+        Err(_) => word.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(input: &str) -> Vec<TokenType> {
+        tokenize(input).unwrap().iter().map(|token| token.t_type).collect()
+    }
+
+    fn texts(input: &str) -> Vec<String> {
+        tokenize(input).unwrap().into_iter().map(|token| token.text).collect()
+    }
+
+    #[test]
+    fn quoted_operators_stay_in_the_word() {
+        assert_eq!(texts("echo \"a|b\" 'c;d' e\\&f"), vec!["echo", "\"a|b\"", "'c;d'", "e\\&f"]);
+        assert_eq!(types("echo \"a|b\""), vec![TokenType::Word, TokenType::Word]);
+    }
+
+    #[test]
+    fn quoted_and_unquoted_parts_are_one_word() {
+        assert_eq!(texts("a'b c'\"d e\"$(f g)${h}"), vec!["a'b c'\"d e\"$(f g)${h}"]);
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(types("a && b || c ; d | e &"), vec![
+            TokenType::Word, TokenType::AndIf, TokenType::Word, TokenType::OrIf, TokenType::Word,
+            TokenType::Semi, TokenType::Word, TokenType::Pipe, TokenType::Word, TokenType::Amp
+        ]);
+
+        assert_eq!(types("a>>b<c>|d<>e;;"), vec![
+            TokenType::Word, TokenType::DGreat, TokenType::Word, TokenType::Less, TokenType::Word,
+            TokenType::Clobber, TokenType::Word, TokenType::LessGreat, TokenType::Word, TokenType::DSemi
+        ]);
+    }
+
+    #[test]
+    fn io_numbers() {
+        assert_eq!(types("cmd 2>file"), vec![TokenType::Word, TokenType::IoNumber, TokenType::Great, TokenType::Word]);
+        assert_eq!(types("cmd 2>&1"), vec![TokenType::Word, TokenType::IoNumber, TokenType::GreatAnd, TokenType::Word]);
+
+        // only digits right before the operator say which fd it is
+        assert_eq!(types("cmd 2 >file"), vec![TokenType::Word, TokenType::Word, TokenType::Great, TokenType::Word]);
+        assert_eq!(types("cmd a2>file"), vec![TokenType::Word, TokenType::Word, TokenType::Great, TokenType::Word]);
+    }
+
+    #[test]
+    fn newlines_and_comments() {
+        assert_eq!(types("a # b | c\nd"), vec![TokenType::Word, TokenType::Newline, TokenType::Word]);
+        assert_eq!(texts("a\\\nb"), vec!["a\\\nb"]);
+        assert_eq!(texts("a \\\n b"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = tokenize("ls  -l|wc").unwrap();
+        let spans: Vec<(usize, usize)> = tokens.iter().map(|token| (token.start, token.end)).collect();
+
+        assert_eq!(spans, vec![(0, 2), (4, 6), (6, 7), (7, 9)]);
+    }
+
+    #[test]
+    fn unclosed_quotes() {
+        assert_eq!(tokenize("echo a\"bc").unwrap_err().0, 6);
+        assert_eq!(tokenize("echo $(ls").unwrap_err().0, 5);
+        assert!(tokenize("echo 'a\"'").is_ok());
+
+        let tokens = tokenize_partial("echo \"a | b");
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[1].text, "\"a | b");
+    }
+
+    #[test]
+    fn ansi_c_quotes() {
+        assert_eq!(texts("echo $'a\\'b' c"), vec!["echo", "$'a\\'b'", "c"]);
+        assert_eq!(texts("echo \"$'\" c"), vec!["echo", "\"$'\"", "c"]);
+    }
+
+    #[test]
+    fn part_ends() {
+        assert_eq!(part_end("'ab'c", 0), 4);
+        assert_eq!(part_end("x$(a (b) c)y", 1), 11);
+        assert_eq!(part_end("${a:-}}", 0), 6);
+        assert_eq!(part_end("\"open", 0), 5);
+    }
+}
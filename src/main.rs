@@ -1,428 +1,1667 @@
 mod builtins;
 mod config;
 mod symbol_table;
-mod tree;
+mod lexer;
 mod parser;
+mod expand;
+mod glob;
 mod keywords;
 mod args;
 mod log;
+mod history;
+mod completion;
+mod vi;
+mod keymap;
+mod graphemes;
+mod editor;
+mod prompt;
+mod highlight;
+mod tty;
 
 use std::process::Command;
-use std::io::{self, Write, BufRead};
+use std::io::{self, Read, Write};
 use std::env;
-use std::process::Stdio;
 use std::fs::{File, OpenOptions};
-use std::os::unix::io::{AsRawFd, FromRawFd};
-use termion::raw::IntoRawMode;
-use termion::input::TermRead;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use nix::fcntl::{FcntlArg, OFlag};
+use nix::sys::signal::{self, SigHandler, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{ForkResult, Pid};
+use parser::RedirectKind;
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::event::{Event, Key};
 use termion::{cursor, terminal_size};
 
-fn print_prompt1(cfg: &config::Config) {
-    print!("{}", cfg.variables.get("PS1").unwrap());
+const KILL_RING_MAX: usize = 60;
+
+// what the terminal wraps pasted text in once bracketed paste is enabled
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+// prints the expanded prompt and returns it along with the expanded RPS1, the line
+// editor needs both to redraw
+fn print_prompt1(cfg: &mut config::Config) -> (String, String) {
+    let ps1 = cfg.variables.get("PS1").unwrap().clone();
+    let rendered = prompt::render(cfg, &ps1);
+
+    let rps1 = cfg.variables.get("RPS1").cloned().unwrap_or_default();
+    let rendered_right = prompt::render(cfg, &rps1);
+
+    print!("{}", prompt::printable(&rendered));
     io::stdout().flush().unwrap();
+
+    (rendered, rendered_right)
 }
 
-fn print_prompt2(cfg: &config::Config) {
-    print!("{}", cfg.variables.get("PS2").unwrap());
-    io::stdout().flush().unwrap();
+// the expanded PS2, the line editor puts it in front of every continuation line
+fn render_prompt2(cfg: &mut config::Config) -> String {
+    let ps2 = cfg.variables.get("PS2").unwrap().clone();
+    prompt::render(cfg, &ps2)
+}
+
+// everything read_raw keeps track of while a line is being edited
+struct LineState {
+    line: String,
+    prompt: String,
+
+    // RPS1, shown at the right end of the first row of input
+    rprompt: String,
+
+    // PS2, in front of every line after the first one
+    ps2: String,
+
+    // byte offset in line where the next character should be inserted, always on
+    // a grapheme boundary
+    insert_cur: usize,
+
+    // terminal row the prompt starts on
+    start_y: u16,
+
+    // position while walking through history, history.len() is the line being typed
+    hist_idx: usize,
+    draft: String,
+
+    // where the last yank started and how far back in the kill ring it went,
+    // so that Alt-Y can swap it for older kills
+    yank_state: Option<(usize, usize)>,
+
+    // set when the line was thrown away with Ctrl-C
+    interrupted: bool,
+
+    // set when Ctrl-D was pressed on an empty line or stdin ran out
+    eof: bool,
+
+    // set once the line is done, nothing but the line itself gets drawn then
+    finished: bool,
+
+    // vi mode only, set while in normal mode
+    vi_normal: bool,
+
+    // the line and cursor before each change, and the ones undone since the last change
+    undo_stack: Vec<(String, usize)>,
+    redo_stack: Vec<(String, usize)>,
+
+    // set while inserts can still be added to the last undo step
+    undo_group: bool,
+}
+
+fn key_backspace(state: &mut LineState) {
+    if state.insert_cur == 0 {
+        return;
+    }
+
+    let start = graphemes::prev_boundary(&state.line, state.insert_cur);
+    state.line.replace_range(start..state.insert_cur, "");
+    state.insert_cur = start;
+}
+
+fn key_delete(state: &mut LineState) {
+    if state.insert_cur == state.line.len() {
+        return;
+    }
+
+    let end = graphemes::next_boundary(&state.line, state.insert_cur);
+    state.line.replace_range(state.insert_cur..end, "");
+}
+
+fn key_left_arrow(state: &mut LineState) {
+    state.insert_cur = graphemes::prev_boundary(&state.line, state.insert_cur);
+}
+
+// the rest of a history entry shown dimmed after the line, only while the cursor
+// is at the end of it
+fn suggestion(cfg: &config::Config, state: &LineState) -> Option<String> {
+    if state.insert_cur < state.line.len() || state.vi_normal || state.finished {
+        return None;
+    }
+
+    history::suggest(cfg, &state.line)
+}
+
+// takes the whole suggestion or just its first word, returns whether there was one
+fn accept_suggestion(cfg: &config::Config, state: &mut LineState, word_only: bool) -> bool {
+    let suggested = match suggestion(cfg, state) {
+        Some(suggested) => suggested,
+        None => return false
+    };
+
+    let end = if word_only { next_word_end(&suggested, 0) } else { suggested.len() };
+
+    state.line.push_str(&suggested[..end]);
+    state.insert_cur = state.line.len();
+    true
+}
+
+fn key_right_arrow(cfg: &config::Config, state: &mut LineState) {
+    if accept_suggestion(cfg, state, false) {
+        return;
+    }
+
+    state.insert_cur = graphemes::next_boundary(&state.line, state.insert_cur);
+}
+
+// the text as it's drawn, with PS2 in front of each line after the first
+fn displayed(state: &LineState, text: &str) -> String {
+    text.replace('\n', format!("\n{}", state.ps2).as_str())
+}
+
+// replaces the word right before the cursor with what it abbreviates, when it's
+// where a command goes
+fn expand_abbreviation(cfg: &config::Config, state: &mut LineState) {
+    let end = state.insert_cur;
+
+    // not when the cursor is in the middle of a word
+    if state.line[end..].starts_with(|ch: char| !ch.is_whitespace()) {
+        return;
+    }
+
+    let start = completion::word_start(&state.line, end);
+
+    let expansion = match cfg.abbreviations.get(&state.line[start..end]) {
+        Some(expansion) => expansion.clone(),
+        None => return
+    };
+
+    if !completion::is_command_position(cfg, &state.line[..start]) {
+        return;
+    }
+
+    // undoing it brings the abbreviation back
+    save_undo(state);
+
+    state.line.replace_range(start..end, expansion.as_str());
+    state.insert_cur = start + expansion.len();
+}
+
+// Enter runs the line, unless the parser says there's more to come, then a
+// continuation line is started instead, returns whether the line was accepted
+fn key_enter(state: &mut LineState) -> bool {
+    if !parser::is_incomplete(&state.line) {
+        return true;
+    }
+
+    state.insert_cur = state.line.len();
+    key_char(state, '\n');
+    false
+}
+
+// moves to about the same column on the line above or below in a multi-line buffer,
+// returns false when there's no such line so that history gets walked instead
+fn move_vertically(state: &mut LineState, up: bool) -> bool {
+    let line_start = state.line[..state.insert_cur].rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+    let column = graphemes::display_width(&state.line[line_start..state.insert_cur]);
+
+    let target_start = if up {
+        if line_start == 0 {
+            return false;
+        }
+
+        state.line[..line_start - 1].rfind('\n').map(|idx| idx + 1).unwrap_or(0)
+    }
+
+    else {
+        match state.line[state.insert_cur..].find('\n') {
+            Some(idx) => state.insert_cur + idx + 1,
+            None => return false
+        }
+    };
+
+    let target_end = state.line[target_start..].find('\n').map(|idx| target_start + idx).unwrap_or(state.line.len());
+
+    let mut idx = target_start;
+    while idx < target_end {
+        let next = graphemes::next_boundary(&state.line, idx);
+
+        if graphemes::display_width(&state.line[target_start..next]) > column {
+            break;
+        }
+
+        idx = next;
+    }
+
+    state.insert_cur = idx;
+    true
+}
+
+fn replace_line(state: &mut LineState, new_line: &str) {
+    state.line = new_line.to_string();
+    state.insert_cur = state.line.len();
+}
+
+fn key_up(cfg: &config::Config, state: &mut LineState) {
+    if move_vertically(state, true) {
+        return;
+    }
+
+    if state.hist_idx == 0 {
+        return;
+    }
+
+    // leaving the line being typed, keep it so that Down can bring it back
+    if state.hist_idx == cfg.history.len() {
+        state.draft = state.line.clone();
+    }
+
+    state.hist_idx -= 1;
+    replace_line(state, &cfg.history[state.hist_idx].line);
+}
+
+fn key_down(cfg: &config::Config, state: &mut LineState) {
+    if move_vertically(state, false) {
+        return;
+    }
+
+    if state.hist_idx >= cfg.history.len() {
+        return;
+    }
+
+    state.hist_idx += 1;
+
+    let new_line = if state.hist_idx == cfg.history.len() {
+        state.draft.clone()
+    }
+
+    else {
+        cfg.history[state.hist_idx].line.clone()
+    };
+
+    replace_line(state, &new_line);
+}
+
+// the closest entry before or after the current one that starts with what's before
+// the cursor, the cursor stays where it is like in readline's history-search-backward
+fn key_history_search(cfg: &config::Config, state: &mut LineState, backward: bool) {
+    let prefix = state.line[..state.insert_cur].to_string();
+    let matches = |idx: &usize| cfg.history[*idx].line.starts_with(&prefix) && cfg.history[*idx].line != state.line;
+
+    let found = if backward {
+        (0..state.hist_idx).rev().find(matches)
+    }
+
+    else {
+        (state.hist_idx + 1..cfg.history.len()).find(matches)
+    };
+
+    let new_line = match found {
+        Some(idx) => {
+            if state.hist_idx == cfg.history.len() {
+                state.draft = state.line.clone();
+            }
+
+            state.hist_idx = idx;
+            cfg.history[idx].line.clone()
+        },
+        // searching forward past the last match goes back to the line being typed
+        None if !backward && state.hist_idx < cfg.history.len() => {
+            state.hist_idx = cfg.history.len();
+            state.draft.clone()
+        },
+        None => return
+    };
+
+    state.line = new_line;
+    state.insert_cur = if state.line.starts_with(&prefix) { prefix.len() } else { state.line.len() };
+}
+
+fn key_home(state: &mut LineState) {
+    state.insert_cur = 0;
+}
+
+fn key_end(cfg: &config::Config, state: &mut LineState) {
+    if accept_suggestion(cfg, state, false) {
+        return;
+    }
+
+    state.insert_cur = state.line.len();
+}
+
+// start of the word before the cursor, words being runs of alphanumeric characters
+fn prev_word_start(line: &str, ins_cur: usize) -> usize {
+    let mut idx = ins_cur;
+    let mut in_word = false;
+
+    for (pos, ch) in line[..ins_cur].char_indices().rev() {
+        if ch.is_alphanumeric() {
+            in_word = true;
+        }
+
+        else if in_word {
+            break;
+        }
+
+        idx = pos;
+    }
+
+    idx
+}
+
+fn next_word_end(line: &str, ins_cur: usize) -> usize {
+    let mut in_word = false;
+
+    for (pos, ch) in line[ins_cur..].char_indices() {
+        if ch.is_alphanumeric() {
+            in_word = true;
+        }
+
+        else if in_word {
+            return ins_cur + pos;
+        }
+    }
+
+    line.len()
+}
+
+fn key_word_left(state: &mut LineState) {
+    state.insert_cur = prev_word_start(&state.line, state.insert_cur);
+}
+
+fn key_word_right(cfg: &config::Config, state: &mut LineState) {
+    if accept_suggestion(cfg, state, true) {
+        return;
+    }
+
+    state.insert_cur = next_word_end(&state.line, state.insert_cur);
+}
+
+// keeps the line as it is now so that the next change can be undone
+fn save_undo(state: &mut LineState) {
+    state.undo_stack.push((state.line.clone(), state.insert_cur));
+    state.redo_stack.clear();
+    state.undo_group = false;
+}
+
+// called after every editor action with the line from before it, characters typed
+// one after another are grouped and undone all at once
+fn record_undo(state: &mut LineState, before: (String, usize), group: bool) {
+    if state.line == before.0 {
+        // moving the cursor around starts a new group
+        if state.insert_cur != before.1 {
+            state.undo_group = false;
+        }
+
+        return;
+    }
+
+    if (!group || !state.undo_group) && state.undo_stack.last() != Some(&before) {
+        state.undo_stack.push(before);
+    }
+
+    state.redo_stack.clear();
+    state.undo_group = group;
+}
+
+fn key_undo(state: &mut LineState) {
+    if let Some((line, insert_cur)) = state.undo_stack.pop() {
+        state.redo_stack.push((std::mem::replace(&mut state.line, line), state.insert_cur));
+        state.insert_cur = insert_cur;
+    }
+
+    state.undo_group = false;
+}
+
+fn key_redo(state: &mut LineState) {
+    if let Some((line, insert_cur)) = state.redo_stack.pop() {
+        state.undo_stack.push((std::mem::replace(&mut state.line, line), state.insert_cur));
+        state.insert_cur = insert_cur;
+    }
+
+    state.undo_group = false;
+}
+
+fn push_kill_ring(cfg: &mut config::Config, text: String) {
+    cfg.kill_ring.push(text);
+
+    if cfg.kill_ring.len() > KILL_RING_MAX {
+        cfg.kill_ring.remove(0);
+    }
+}
+
+// removes start..end from the line and saves it in the kill ring for Ctrl-Y
+fn kill_text(cfg: &mut config::Config, state: &mut LineState, start: usize, end: usize) {
+    if start >= end {
+        return;
+    }
+
+    let killed: String = state.line.drain(start..end).collect();
+    push_kill_ring(cfg, killed);
+
+    state.insert_cur = start;
+}
+
+fn key_kill_word_back(cfg: &mut config::Config, state: &mut LineState) {
+    // unlike Alt-B, Ctrl-W only stops at whitespace
+    let end = state.insert_cur;
+    let before = state.line[..end].trim_end();
+
+    let start = match before.rfind(char::is_whitespace) {
+        Some(idx) => idx + before[idx..].chars().next().unwrap().len_utf8(),
+        None => 0
+    };
+
+    kill_text(cfg, state, start, end);
+}
+
+fn key_kill_word_forward(cfg: &mut config::Config, state: &mut LineState) {
+    let start = state.insert_cur;
+    let end = next_word_end(&state.line, start);
+    kill_text(cfg, state, start, end);
+}
+
+fn key_kill_to_end(cfg: &mut config::Config, state: &mut LineState) {
+    let start = state.insert_cur;
+    let end = state.line.len();
+    kill_text(cfg, state, start, end);
+}
+
+fn key_kill_to_start(cfg: &mut config::Config, state: &mut LineState) {
+    let end = state.insert_cur;
+    kill_text(cfg, state, 0, end);
+}
+
+fn key_yank(cfg: &config::Config, state: &mut LineState) {
+    let text = match cfg.kill_ring.last() {
+        Some(text) => text,
+        None => return
+    };
+
+    let start = state.insert_cur;
+    state.line.insert_str(start, text.as_str());
+
+    state.yank_state = Some((start, 0));
+    state.insert_cur = start + text.len();
+}
+
+fn key_yank_pop(cfg: &config::Config, state: &mut LineState) {
+    let (start, ring_offset) = match state.yank_state {
+        Some(yank_state) => yank_state,
+        None => return
+    };
+
+    let ring_offset = (ring_offset + 1) % cfg.kill_ring.len();
+    let text = &cfg.kill_ring[cfg.kill_ring.len() - 1 - ring_offset];
+
+    state.line.replace_range(start..state.insert_cur, text.as_str());
+
+    state.yank_state = Some((start, ring_offset));
+    state.insert_cur = start + text.len();
+}
+
+fn key_transpose(state: &mut LineState) {
+    // at the end of the line the last two characters are swapped instead
+    let idx = if state.insert_cur == state.line.len() {
+        graphemes::prev_boundary(&state.line, state.insert_cur)
+    }
+
+    else {
+        state.insert_cur
+    };
+
+    if idx == 0 {
+        return;
+    }
+
+    let start = graphemes::prev_boundary(&state.line, idx);
+    let end = graphemes::next_boundary(&state.line, idx);
+
+    let swapped = format!("{}{}", &state.line[idx..end], &state.line[start..idx]);
+    state.line.replace_range(start..end, swapped.as_str());
+
+    state.insert_cur = end;
+}
+
+fn key_clear_screen(cfg: &config::Config, stdout: &mut RawTerminal<io::Stdout>, state: &mut LineState) {
+    write!(stdout, "{}", termion::clear::All).unwrap();
+
+    state.start_y = 1;
+    redraw(cfg, stdout, state);
+}
+
+// redraws everything from the start of row start_y, keeping start_y correct if the
+// terminal had to scroll, and leaves the cursor right after before_cursor, which
+// is what text starts with minus any colours, returns where that is
+fn draw_from(stdout: &mut RawTerminal<io::Stdout>, start_y: &mut u16, text: &str, before_cursor: &str) -> (u16, u16) {
+    let (terminal_cols, terminal_lines) = terminal_size().unwrap();
+
+    write!(stdout, "{}{}{}", cursor::Goto(1, *start_y), termion::clear::AfterCursor, prompt::printable(text).replace('\n', "\r\n")).unwrap();
+
+    let (end_row, end_col) = graphemes::position_after(text, terminal_cols as usize);
+
+    // get out of the last column, otherwise the terminal leaves the cursor pending a wrap
+    if end_row > 0 && end_col == 0 && !text.ends_with('\n') {
+        write!(stdout, "\r\n").unwrap();
+    }
+
+    let end_y = *start_y as usize + end_row;
+    if end_y > terminal_lines as usize {
+        *start_y = start_y.saturating_sub((end_y - terminal_lines as usize) as u16).max(1);
+    }
+
+    let (cur_row, cur_col) = graphemes::position_after(before_cursor, terminal_cols as usize);
+    let (cur_x, cur_y) = (cur_col as u16 + 1, *start_y + cur_row as u16);
+
+    write!(stdout, "{}", cursor::Goto(cur_x, cur_y)).unwrap();
+    stdout.flush().unwrap();
+
+    (cur_x, cur_y)
+}
+
+fn redraw(cfg: &config::Config, stdout: &mut RawTerminal<io::Stdout>, state: &mut LineState) {
+    let suggested = suggestion(cfg, state).unwrap_or_default();
+
+    let text = format!("{}{}", state.prompt, displayed(state, format!("{}{}", state.line, suggested).as_str()));
+    let mut highlighted = format!("{}{}", state.prompt, displayed(state, highlight::highlight(cfg, &state.line).as_str()));
+
+    if !suggested.is_empty() {
+        highlighted.push_str(format!("{}{}{}", termion::style::Faint, displayed(state, &suggested), termion::style::Reset).as_str());
+    }
+    let before_cursor = format!("{}{}", state.prompt, displayed(state, &state.line[..state.insert_cur]));
+
+    let (cur_x, cur_y) = draw_from(stdout, &mut state.start_y, highlighted.as_str(), before_cursor.as_str());
+
+    if state.rprompt.is_empty() {
+        return;
+    }
+
+    let (terminal_cols, _terminal_lines) = terminal_size().unwrap();
+    let terminal_cols = terminal_cols as usize;
+
+    let (prompt_row, _prompt_col) = graphemes::position_after(&state.prompt, terminal_cols);
+    let (end_row, end_col) = graphemes::position_after(&text, terminal_cols);
+    let rprompt_width = graphemes::display_width(&state.rprompt);
+
+    // it goes away once the input wraps or gets within a column of it, the last
+    // column is left empty so that the terminal doesn't wrap
+    if end_row != prompt_row || end_col + rprompt_width + 2 > terminal_cols {
+        return;
+    }
+
+    let rprompt_x = (terminal_cols - rprompt_width) as u16;
+    let rprompt_y = state.start_y + prompt_row as u16;
+
+    write!(stdout, "{}{}{}", cursor::Goto(rprompt_x, rprompt_y), prompt::printable(&state.rprompt), cursor::Goto(cur_x, cur_y)).unwrap();
+    stdout.flush().unwrap();
+}
+
+fn key_tab(cfg: &mut config::Config, stdout: &mut RawTerminal<io::Stdout>, state: &mut LineState) {
+    let ins_cur = state.insert_cur;
+    let candidates = completion::complete(cfg, &state.line, ins_cur);
+
+    if candidates.is_empty() {
+        return;
+    }
+
+    let start = completion::word_start(&state.line, ins_cur);
+    let mut replacement = completion::common_prefix(&candidates);
+
+    if candidates.len() == 1 && !replacement.ends_with('/') {
+        replacement.push(' ');
+    }
+
+    if replacement.len() > ins_cur - start {
+        state.line.replace_range(start..ins_cur, replacement.as_str());
+        state.insert_cur = start + replacement.len();
+        return;
+    }
+
+    if candidates.len() == 1 {
+        return;
+    }
+
+    // nothing left to insert, list the candidates under the line and redraw it
+    let (terminal_cols, terminal_lines) = terminal_size().unwrap();
+
+    let (end_row, end_col) = graphemes::position_after(format!("{}{}", state.prompt, displayed(state, &state.line)).as_str(), terminal_cols as usize);
+    let mut list_y = state.start_y + end_row as u16;
+
+    write!(stdout, "{}", cursor::Goto(1, list_y)).unwrap();
+
+    if end_col != 0 {
+        write!(stdout, "\r\n").unwrap();
+        list_y += 1;
+    }
+
+    let rows = completion::format_columns(&candidates, terminal_cols as usize);
+
+    for row in rows.iter() {
+        write!(stdout, "{}\r\n", row).unwrap();
+    }
+
+    state.start_y = (list_y + rows.len() as u16).min(terminal_lines);
+    redraw(cfg, stdout, state);
 }
 
-fn key_backspace(cfg: &config::Config, line: &mut String, cur_x: &mut u16, cur_y: &mut u16, ins_cur: &mut u32) {
-    if *ins_cur == 0 {
-        return;
+// readline style incremental search, returns the entry that was picked (if any)
+// and whether the line should be executed right away
+fn search_history(cfg: &config::Config, stdout: &mut RawTerminal<io::Stdout>, events: &mut tty::Events, start_y: &mut u16, hist_idx: usize, reverse: bool) -> (Option<usize>, bool, Option<Key>) {
+    let mut reverse = reverse;
+    let mut query = String::new();
+    let mut found: Option<usize> = None;
+    let mut failed = false;
+
+    // entries older than the one being shown for reverse searches, newer ones otherwise
+    let first_from = |reverse: bool| if reverse { hist_idx.checked_sub(1) } else { Some(hist_idx + 1) };
+
+    loop {
+        let matched = found.map(|idx| cfg.history[idx].line.as_str()).unwrap_or("");
+        let status = format!("({}{}i-search)`{}': ", if failed { "failed " } else { "" }, if reverse { "reverse-" } else { "" }, query);
+        let match_offset = if query.is_empty() { 0 } else { matched.find(query.as_str()).unwrap_or(0) };
+
+        draw_from(stdout, start_y, format!("{}{}", status, matched).as_str(), format!("{}{}", status, &matched[..match_offset]).as_str());
+
+        let key = match next_key(events) {
+            Some(key) => key,
+            None => return (found, false, None)
+        };
+
+        let from = match key {
+            Key::Char('\n') => return (found, true, None),
+            Key::Esc | Key::Ctrl('g') | Key::Ctrl('c') => return (None, false, None),
+            Key::Char(ch) => {
+                query.push(ch);
+                found.or(first_from(reverse))
+            },
+            Key::Backspace => {
+                query.pop();
+                found = None;
+                first_from(reverse)
+            },
+            Key::Ctrl('r') => {
+                reverse = true;
+                found.map_or(first_from(reverse), |idx| idx.checked_sub(1))
+            },
+            Key::Ctrl('s') => {
+                reverse = false;
+                found.map_or(first_from(reverse), |idx| Some(idx + 1))
+            },
+            // any other key ends the search, leaves the match in the buffer and then
+            // does what it normally does
+            _ => return (found, false, Some(key))
+        };
+
+        if query.is_empty() {
+            failed = false;
+            continue;
+        }
+
+        match from.and_then(|from| history::search(cfg, query.as_str(), from, reverse)) {
+            Some(idx) => {
+                found = Some(idx);
+                failed = false;
+            },
+            None => failed = true
+        }
+    }
+}
+
+// runs search_history from the current line and puts the prompt back afterwards,
+// returns whether the line should be executed right away
+fn key_search_history(cfg: &mut config::Config, stdout: &mut RawTerminal<io::Stdout>, events: &mut tty::Events, state: &mut LineState, reverse: bool) -> bool {
+    let (found, execute, key) = search_history(cfg, stdout, events, &mut state.start_y, state.hist_idx, reverse);
+
+    if let Some(idx) = found {
+        if state.hist_idx == cfg.history.len() {
+            state.draft = state.line.clone();
+        }
+
+        state.hist_idx = idx;
+        state.line = cfg.history[idx].line.clone();
+    }
+
+    state.insert_cur = state.line.len();
+    redraw(cfg, stdout, state);
+
+    match key {
+        Some(key) if state.vi_normal => vi::normal_mode_key(cfg, stdout, events, state, key),
+        Some(key) => dispatch_key(cfg, stdout, events, state, key),
+        None => execute
+    }
+}
+
+// runs whatever key is bound to, reading the rest of the sequence when it's the start
+// of a longer one, returns whether the line was accepted
+fn dispatch_key(cfg: &mut config::Config, stdout: &mut RawTerminal<io::Stdout>, events: &mut tty::Events, state: &mut LineState, key: Key) -> bool {
+    let mut pending = vec![key];
+
+    loop {
+        match keymap::lookup(cfg, &pending) {
+            keymap::Lookup::Action(action) => return run_action(cfg, stdout, events, state, action.as_str(), *pending.last().unwrap()),
+            keymap::Lookup::Prefix => match next_key(events) {
+                Some(key) => pending.push(key),
+                None => return false
+            },
+            keymap::Lookup::Unbound => return false
+        }
+    }
+}
+
+// next key pressed, skipping mouse events and sequences termion doesn't know about
+fn next_key(events: &mut tty::Events) -> Option<Key> {
+    for event in events.by_ref() {
+        if let Ok(Event::Key(key)) = event {
+            return Some(key);
+        }
+    }
+
+    None
+}
+
+// everything up to the end of a bracketed paste, newlines are kept as they are
+// instead of accepting the line
+fn read_paste(events: &mut tty::Events) -> String {
+    let mut text = String::new();
+
+    for event in events.by_ref() {
+        match event {
+            Ok(Event::Key(Key::Char(ch))) => text.push(ch),
+            Ok(Event::Unsupported(bytes)) if bytes == PASTE_END => break,
+            Ok(_) => {},
+            Err(e) if tty::is_resize(&e) => {},
+            Err(_) => break
+        }
+    }
+
+    text
+}
+
+// hands the line over to $VISUAL or $EDITOR, returns whether what comes back should
+// be executed right away
+fn key_edit_line(cfg: &config::Config, stdout: &mut RawTerminal<io::Stdout>, state: &mut LineState, execute: bool) -> bool {
+    state.insert_cur = state.line.len();
+    redraw(cfg, stdout, state);
+
+    write!(stdout, "\x1b[?2004l\r\n").unwrap();
+    stdout.flush().unwrap();
+    stdout.suspend_raw_mode().unwrap();
+
+    let result = editor::edit_text(cfg, editor::default_editor(cfg).as_str(), &state.line);
+
+    stdout.activate_raw_mode().unwrap();
+    write!(stdout, "\x1b[?2004h").unwrap();
+
+    if config::option_enabled(cfg, "vi") {
+        if state.vi_normal {
+            write!(stdout, "{}", cursor::SteadyBlock).unwrap();
+        }
+
+        else {
+            write!(stdout, "{}", cursor::SteadyBar).unwrap();
+        }
+    }
+
+    let edited = match result {
+        Ok(edited) => Some(edited),
+        Err(e) => {
+            write!(stdout, "rsh: {}\r\n", e).unwrap();
+            None
+        }
+    };
+
+    // the prompt is drawn again wherever the editor left the cursor
+    let (_cur_x, cur_y) = cursor::DetectCursorPos::cursor_pos(stdout).unwrap();
+    state.start_y = cur_y;
+
+    match edited {
+        Some(edited) => {
+            state.line = edited;
+            state.insert_cur = state.line.len();
+
+            if state.vi_normal {
+                state.insert_cur = graphemes::prev_boundary(&state.line, state.insert_cur);
+            }
+
+            execute && !state.line.trim().is_empty()
+        },
+        None => false
+    }
+}
+
+// the terminal may have rewrapped the line on its own, so where the prompt starts now
+// is worked out from where the cursor ended up
+fn key_resize(cfg: &mut config::Config, stdout: &mut RawTerminal<io::Stdout>, state: &mut LineState) {
+    tty::update_size_variables(cfg);
+
+    let (terminal_cols, _terminal_lines) = terminal_size().unwrap();
+    let (_cur_x, cur_y) = cursor::DetectCursorPos::cursor_pos(stdout).unwrap();
+
+    let before_cursor = format!("{}{}", state.prompt, displayed(state, &state.line[..state.insert_cur]));
+    let (cur_row, _cur_col) = graphemes::position_after(&before_cursor, terminal_cols as usize);
+
+    state.start_y = cur_y.saturating_sub(cur_row as u16).max(1);
+    log::debug(cfg, format!("resized to {} columns, prompt now on row {}", terminal_cols, state.start_y).as_str());
+
+    redraw(cfg, stdout, state);
+}
+
+fn key_char(state: &mut LineState, ch: char) {
+    state.line.insert(state.insert_cur, ch);
+    state.insert_cur += ch.len_utf8();
+}
+
+// runs one of keymap::ACTIONS, returns whether the line was accepted
+fn run_action(cfg: &mut config::Config, stdout: &mut RawTerminal<io::Stdout>, events: &mut tty::Events, state: &mut LineState, action: &str, key: Key) -> bool {
+    // yank-pop only makes sense right after a yank
+    if action != "yank" && action != "yank-pop" {
+        state.yank_state = None;
+    }
+
+    match action {
+        "accept-line" => {
+            expand_abbreviation(cfg, state);
+            return key_enter(state);
+        },
+        "self-insert" => {
+            if let Key::Char(ch) = key {
+                if ch == ' ' {
+                    expand_abbreviation(cfg, state);
+                }
+
+                key_char(state, ch);
+            }
+        },
+        "complete" => key_tab(cfg, stdout, state),
+        "backward-delete-char" => key_backspace(state),
+        "delete-char" => {
+            // same as readline, the eof char on an empty line ends the input
+            if key == Key::Ctrl('d') && state.line.is_empty() {
+                state.eof = true;
+                return true;
+            }
+
+            key_delete(state);
+        },
+        "backward-char" => key_left_arrow(state),
+        "forward-char" => key_right_arrow(cfg, state),
+        "previous-history" => key_up(cfg, state),
+        "next-history" => key_down(cfg, state),
+        "history-search-backward" => key_history_search(cfg, state, true),
+        "history-search-forward" => key_history_search(cfg, state, false),
+        "beginning-of-line" => key_home(state),
+        "end-of-line" => key_end(cfg, state),
+        "backward-word" => key_word_left(state),
+        "forward-word" => key_word_right(cfg, state),
+        "kill-word" => key_kill_word_forward(cfg, state),
+        "unix-word-rubout" => key_kill_word_back(cfg, state),
+        "kill-line" => key_kill_to_end(cfg, state),
+        "unix-line-discard" => key_kill_to_start(cfg, state),
+        "yank" => key_yank(cfg, state),
+        "yank-pop" => key_yank_pop(cfg, state),
+        "transpose-chars" => key_transpose(state),
+        "undo" => key_undo(state),
+        "redo" => key_redo(state),
+        "clear-screen" => key_clear_screen(cfg, stdout, state),
+        "edit-and-execute-command" | "edit-and-execute" => return key_edit_line(cfg, stdout, state, true),
+        "edit-command-line" => return key_edit_line(cfg, stdout, state, false),
+        "reverse-search-history" => return key_search_history(cfg, stdout, events, state, true),
+        "forward-search-history" => return key_search_history(cfg, stdout, events, state, false),
+        "vi-movement-mode" => {
+            if config::option_enabled(cfg, "vi") {
+                vi::enter_normal_mode(stdout, state);
+            }
+        },
+        _ => log::debug(cfg, format!("unknown editor action {}", action).as_str())
+    }
+
+    false
+}
+
+// reads a line after prompt has already been printed, rprompt is drawn on the right,
+// None on end of input
+fn read_raw(cfg: &mut config::Config, prompt: &str, rprompt: &str) -> Option<String> {
+    let mut stdout = io::stdout().into_raw_mode().unwrap();
+
+    // the prompt may have wrapped, start_y is the row it started on
+    let (terminal_cols, _terminal_lines) = terminal_size().unwrap();
+    let (_cur_x, cur_y) = cursor::DetectCursorPos::cursor_pos(&mut stdout).unwrap();
+    let (mut prompt_rows, prompt_col) = graphemes::position_after(prompt, terminal_cols as usize);
+
+    // a prompt that fills its last row exactly leaves the cursor in the last column
+    if prompt_rows > 0 && prompt_col == 0 && !prompt.ends_with('\n') {
+        prompt_rows -= 1;
+    }
+
+    let mut state = LineState {
+        line: String::new(),
+        prompt: prompt.to_string(),
+        rprompt: rprompt.to_string(),
+        ps2: render_prompt2(cfg),
+        insert_cur: 0,
+        start_y: cur_y.saturating_sub(prompt_rows as u16).max(1),
+        hist_idx: cfg.history.len(),
+        draft: String::new(),
+        yank_state: None,
+        interrupted: false,
+        eof: false,
+        finished: false,
+        vi_normal: false,
+        undo_stack: Vec::new(),
+        redo_stack: Vec::new(),
+        undo_group: false,
+    };
+
+    log::debug(cfg, format!("prompt starts on row {}", state.start_y).as_str());
+
+    let vi_mode = config::option_enabled(cfg, "vi");
+
+    if vi_mode {
+        write!(stdout, "{}", cursor::SteadyBar).unwrap();
     }
+
+    // pasted text then comes in between PASTE_START and PASTE_END
+    write!(stdout, "\x1b[?2004h").unwrap();
+    redraw(cfg, &mut stdout, &mut state);
     
-    if *ins_cur == line.len() as u32 {
-        line.pop();
+    tty::update_size_variables(cfg);
+    tty::clear_resize();
+
+    let mut events = tty::input();
+
+    // keys read so far of a sequence that's bound to something, like Ctrl-X Ctrl-E
+    let mut pending: Vec<Key> = Vec::new();
+
+    loop {
+        let event = match events.next() {
+            Some(event) => event,
+            None => {
+                state.eof = true;
+                break;
+            }
+        };
+
+
+        let key = match event {
+            Ok(Event::Key(key)) => key,
+            Ok(Event::Unsupported(bytes)) if bytes == PASTE_START => {
+                let text = read_paste(&mut events);
+
+                // inserted all at once, with a single redraw however big it is
+                save_undo(&mut state);
+                state.line.insert_str(state.insert_cur, text.as_str());
+                state.insert_cur += text.len();
+                pending.clear();
+
+                redraw(cfg, &mut stdout, &mut state);
+                continue;
+            },
+            Err(e) if tty::is_resize(&e) => {
+                key_resize(cfg, &mut stdout, &mut state);
+                continue;
+            },
+            Err(e) => {
+                log::debug(cfg, format!("could not read from stdin: {}", e).as_str());
+                state.eof = true;
+                break;
+            },
+            _ => continue
+        };
+
+        if state.vi_normal {
+            if vi::normal_mode_key(cfg, &mut stdout, &mut events, &mut state, key) {
+                break;
+            }
+        }
+
+        // Ctrl-C can't be rebound, it always throws the line away
+        else if key == Key::Ctrl('c') {
+            state.interrupted = true;
+            break;
+        }
+
+        else {
+            pending.push(key);
+
+            let action = match keymap::lookup(cfg, &pending) {
+                keymap::Lookup::Action(action) => action,
+                keymap::Lookup::Prefix => continue,
+                keymap::Lookup::Unbound => {
+                    let single_char = pending.len() == 1 && matches!(key, Key::Char(_));
+                    pending.clear();
+
+                    if !single_char {
+                        continue;
+                    }
+
+                    String::from("self-insert")
+                }
+            };
+
+            pending.clear();
+
+            let before = (state.line.clone(), state.insert_cur);
+            let accepted = run_action(cfg, &mut stdout, &mut events, &mut state, action.as_str(), key);
+
+            if action != "undo" && action != "redo" {
+                record_undo(&mut state, before, action == "self-insert");
+            }
+
+            if accepted {
+                break;
+            }
+        }
+
+        redraw(cfg, &mut stdout, &mut state);
     }
 
-    else {
-        line.remove(*ins_cur as usize - 1);
+    // the line is left behind with a compact prompt, to keep the scrollback clean
+    if config::option_enabled(cfg, "transientprompt") {
+        let transient_ps1 = cfg.variables.get("TRANSIENT_PS1").cloned().unwrap_or(String::from("\\$ "));
+        state.prompt = prompt::render(cfg, &transient_ps1);
     }
-    
-    let (terminal_cols, _terminal_lines) = terminal_size().unwrap();
-   
-    log::debug(cfg, format!("cur_x inside key_backspace: {}", *cur_x).as_str());
-    if *cur_x == 1 {
-        *cur_x = terminal_cols;
-        *cur_y -= 1;
+
+    // the output has to start below the whole line, not wherever the cursor was
+    state.finished = true;
+    state.rprompt = String::new();
+    state.insert_cur = state.line.len();
+    redraw(cfg, &mut stdout, &mut state);
+
+    if state.interrupted {
+        write!(stdout, "^C").unwrap();
+        state.line = String::new();
     }
 
-    else {
-        *cur_x -= 1;
+    if vi_mode {
+        write!(stdout, "{}", vi::CURSOR_DEFAULT).unwrap();
     }
-    *ins_cur -= 1;
-}
 
-fn key_delete(line: &mut String, ins_cur: &mut u32) {
-    if *ins_cur as usize == line.len() {
-        return;
+    write!(stdout, "\x1b[?2004l\r\n").unwrap();
+
+    if state.eof {
+        return None;
     }
+
+    Some(state.line)
+}
+
+// None once there's no more input, like Ctrl-D on an empty line
+fn read_command(cfg: &mut config::Config) -> Option<String> {
+    let (prompt, rprompt) = print_prompt1(cfg);
+    let mut line = read_raw(cfg, &prompt, &rprompt)?;
     
-    else {
-        line.remove(*ins_cur as usize);
+    line = line.trim().to_string();
+    
+    log::debug(cfg, format!("line: {}", line).as_str());
+
+    Some(line)
+}
+
+// parses and runs a whole line, returns should_continue and the exit status
+fn execute_line(cfg: &mut config::Config, line: &str) -> (i32, i32) {
+    execute_source(cfg, line, "rsh", None)
+}
+
+// execute_line for lines of a script, a syntax error says which script and line
+// it's on. first_line is the line of the script that line starts on
+fn execute_source(cfg: &mut config::Config, line: &str, source: &str, first_line: Option<usize>) -> (i32, i32) {
+    match parser::build_ast(line, cfg) {
+        Ok(Some(list)) => execute_list(cfg, &list, 0, 1),
+        Ok(None) => (1, 0),
+        Err(e) => {
+            log::debug(cfg, format!("syntax error at bytes {}..{} of {:?}", e.start, e.end, line).as_str());
+            eprintln!("{}", e.render(source, first_line));
+            symbol_table::set_env_var("?", "2", cfg);
+            (1, 2)
+        }
     }
 }
 
-fn key_left_arrow(cfg: &config::Config, cur_x: &mut u16, cur_y: &mut u16, ins_cur: &mut u32) {
-    log::debug(cfg, format!("(inside key_left_arrow) ins_cur: {}", ins_cur).as_str());
+// runs line the way $(...) is run and returns what it printed
+fn capture_output(cfg: &mut config::Config, line: &str) -> Result<String, String> {
+    let (fd_read, fd_write) = nix::unistd::pipe2(OFlag::O_CLOEXEC).map_err(|e| format!("pipe: {}", e))?;
+    let line = line.to_string();
 
-    let (terminal_cols, _terminal_lines) = terminal_size().unwrap();
-    
-    if *cur_x == 1 {
-        *cur_x = terminal_cols + 1;
-        *cur_y -= 1;
+    let pid = fork_shell(cfg, 0, fd_write, Some(fd_read), |cfg| execute_line(cfg, &line).1);
+    nix::unistd::close(fd_write).ok();
+
+    let pid = match pid {
+        Ok(pid) => pid,
+        Err(e) => {
+            nix::unistd::close(fd_read).ok();
+            return Err(e);
+        }
+    };
+
+    let mut output = Vec::new();
+    let mut file = unsafe { File::from_raw_fd(fd_read) };
+    file.read_to_end(&mut output).ok();
+
+    let status = wait_for(Running::Forked(pid));
+    symbol_table::set_env_var("?", &status.to_string(), cfg);
+
+    Ok(String::from_utf8_lossy(&output).trim_end_matches('\n').to_string())
+}
+
+// capture_output for the shell's own use, like prompts and completion functions,
+// $? stays what the user's last command set it to
+fn capture_output_keep_status(cfg: &mut config::Config, line: &str) -> Result<String, String> {
+    let status = cfg.variables.get("?").cloned();
+    let output = capture_output(cfg, line);
+
+    if let Some(status) = status {
+        symbol_table::set_env_var("?", &status, cfg);
     }
-    
-    if *ins_cur > 0 {
-        *ins_cur = *ins_cur - 1;
-        *cur_x -= 1;
+
+    output
+}
+
+// a command of a pipeline that was started and not waited for yet
+enum Running {
+    Child(std::process::Child),
+    Forked(Pid),
+}
+
+// waits for a command to finish and returns its exit status, 128 plus the signal
+// number when a signal killed it
+fn wait_for(running: Running) -> i32 {
+    match running {
+        Running::Child(mut child) => match child.wait() {
+            Ok(status) => status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0)),
+            Err(_) => 1
+        },
+        Running::Forked(pid) => loop {
+            match waitpid(pid, None) {
+                Ok(WaitStatus::Exited(_, status)) => break status,
+                Ok(WaitStatus::Signaled(_, signal, _)) => break 128 + signal as i32,
+                Ok(_) | Err(nix::errno::Errno::EINTR) => continue,
+                Err(_) => break 1
+            }
+        }
     }
 }
 
-fn key_right_arrow(cfg: &config::Config, line: &String, cur_x: &mut u16, cur_y: &mut u16, ins_cur: &mut u32) {
-    log::debug(cfg, format!("(inside key_left_arrow) ins_cur: {}", ins_cur).as_str());
-    
-    if *ins_cur >= line.len() as u32 {
-        return;
+// runs f in a copy of the shell with stdin and stdout pointing at the given fds, the
+// way subshells and the commands of a pipeline run. close is an fd the copy must not
+// keep open, like the read end of the pipe it writes to
+fn fork_shell(cfg: &mut config::Config, stdin: RawFd, stdout: RawFd, close: Option<RawFd>, f: impl FnOnce(&mut config::Config) -> i32) -> Result<Pid, String> {
+    io::stdout().flush().ok();
+
+    match unsafe { nix::unistd::fork() } {
+        Ok(ForkResult::Parent { child }) => Ok(child),
+        Ok(ForkResult::Child) => {
+            // the shell ignores SIGPIPE, a copy of it writing to a closed pipe should just die
+            unsafe { signal::signal(Signal::SIGPIPE, SigHandler::SigDfl) }.ok();
+
+            if let Some(fd) = close {
+                nix::unistd::close(fd).ok();
+            }
+
+            if stdin != 0 {
+                nix::unistd::dup2(stdin, 0).ok();
+            }
+
+            if stdout != 1 {
+                nix::unistd::dup2(stdout, 1).ok();
+            }
+
+            let status = f(cfg);
+            io::stdout().flush().ok();
+
+            std::process::exit(status);
+        },
+        Err(e) => Err(format!("fork: {}", e))
     }
-    
-    let (terminal_cols, _terminal_lines) = terminal_size().unwrap();
-    
-    if *cur_x == terminal_cols {
-        *cur_x = 0;
-        *cur_y += 1; 
+}
+
+// opens what redirects point at and adds (fd, where it should point) pairs to fds,
+// files keeps the opened files alive until the command is done with them
+fn open_redirects(cfg: &mut config::Config, redirects: &[parser::Redirect], fds: &mut Vec<(RawFd, RawFd)>, files: &mut Vec<File>) -> Result<(), String> {
+    for redirect in redirects {
+        let target = expand::expand_word(cfg, &redirect.target.text)?;
+
+        let source = match redirect.kind {
+            RedirectKind::DupInput | RedirectKind::DupOutput => match target.parse::<RawFd>() {
+                Ok(fd) => fd,
+                Err(_) => return Err(format!("{}: ambiguous redirect", target))
+            },
+            kind => {
+                let mut options = OpenOptions::new();
+
+                match kind {
+                    RedirectKind::Input => options.read(true),
+                    RedirectKind::Append => options.append(true).create(true),
+                    RedirectKind::ReadWrite => options.read(true).write(true).create(true),
+                    _ => options.write(true).create(true).truncate(true)
+                };
+
+                let file = options.open(&target).map_err(|e| format!("{}: {}", target, e))?;
+                let fd = file.as_raw_fd();
+
+                files.push(file);
+                fd
+            }
+        };
+
+        fds.push((redirect.fd(), source));
     }
-    
-    *ins_cur += 1 ;
-    *cur_x += 1;
+
+    Ok(())
 }
 
-fn read_raw(cfg: &config::Config) -> String {
-    let mut stdout = io::stdout().into_raw_mode().unwrap();
-    
-    let mut line = String::new();
-    
-    // represents where in the string the next character should be inserted
-    let mut insert_cur = 0;
-    
-    for c in io::stdin().keys() {
-        
-        let (mut cur_x, mut cur_y) = cursor::DetectCursorPos::cursor_pos(&mut stdout).unwrap();
-        
-        match c.unwrap() {
-            termion::event::Key::Char('\n') => break,
-            termion::event::Key::Char(ch) => {
-                let (terminal_cols, _terminal_lines) = terminal_size().unwrap();
-                
-                if (insert_cur as usize) < line.len() {
-                    line.insert(insert_cur as usize, ch);
-                }
-                
-                else {
-                    line.push(ch);
-                }
+// points the shell's own fds where fds says while f runs, builtins write to them directly
+fn with_fds<T>(fds: &[(RawFd, RawFd)], f: impl FnOnce() -> T) -> T {
+    io::stdout().flush().ok();
 
-                insert_cur += 1;
-                
-                if cur_x < terminal_cols {
-                    cur_x += 1;
-                }
+    let saved: Vec<(RawFd, Option<RawFd>)> = fds.iter()
+        .map(|(fd, source)| {
+            let saved = nix::fcntl::fcntl(*fd, FcntlArg::F_DUPFD_CLOEXEC(10)).ok();
+            nix::unistd::dup2(*source, *fd).ok();
+            (*fd, saved)
+        })
+        .collect();
 
-                else {
-                    cur_x = 0;
-                    cur_y += 1;
-                }
-            }, 
-            termion::event::Key::Up => todo!(),
-            termion::event::Key::End => todo!(),
-            termion::event::Key::Esc => continue,
-            termion::event::Key::Home => todo!(),
-            termion::event::Key::Right => key_right_arrow(cfg, &line, &mut cur_x, &mut cur_y, &mut insert_cur),
-            termion::event::Key::Down => todo!(),
-            termion::event::Key::Left => key_left_arrow(cfg, &mut cur_x, &mut cur_y, &mut insert_cur),
-            termion::event::Key::Delete => key_delete(&mut line, &mut insert_cur),
-            termion::event::Key::Null => panic!(),
-            termion::event::Key::Backspace => key_backspace(cfg, &mut line, &mut cur_x, &mut cur_y, &mut insert_cur),
-            termion::event::Key::PageUp => continue,
-            termion::event::Key::PageDown => continue,
-            termion::event::Key::BackTab => continue,
-            termion::event::Key::Insert => continue,
-            termion::event::Key::F(_) => continue,
-            termion::event::Key::Alt(_) => continue,
-            termion::event::Key::Ctrl(seq) => {
-                if seq == 'c' {
-                    line = String::new();
-                    write!(stdout, "^C").unwrap();
-                    break;
-                }
+    let result = f();
+    io::stdout().flush().ok();
+
+    for (fd, saved) in saved.iter().rev() {
+        match saved {
+            Some(saved) => {
+                nix::unistd::dup2(*saved, *fd).ok();
+                nix::unistd::close(*saved).ok();
             },
-            _ => unreachable!()
+            None => {
+                nix::unistd::close(*fd).ok();
+            }
         }
+    }
 
-        let (terminal_cols, terminal_lines) = terminal_size().unwrap();
+    result
+}
 
-        log::debug(cfg, format!("terminal_cols: {}\nterminal_lines: {}\n", terminal_cols, terminal_lines).as_str());
-        log::debug(cfg, format!("line.len: {}\n", line.len()).as_str());
-        
-        
-        let prompt_len = cfg.variables.get("PS1").unwrap().len();
-        
-        let mut cols_to_erase = ((insert_cur as usize + prompt_len ) / terminal_cols as usize > 0) as usize;
-        if (insert_cur as usize + prompt_len) / terminal_cols as usize > 1 {
-            cols_to_erase += (insert_cur as usize + prompt_len) / terminal_cols as usize - 1;
+// starts an external command with its fds pointing where fds says
+fn spawn_external(argv: &[String], env: &[(String, String)], fds: &[(RawFd, RawFd)]) -> Result<std::process::Child, String> {
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]);
+    command.envs(env.iter().map(|(name, value)| (name, value)));
+
+    let fds = fds.to_vec();
+
+    unsafe {
+        command.pre_exec(move || {
+            for (fd, source) in fds.iter() {
+                if fd != source && libc::dup2(*source, *fd) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    command.spawn().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => format!("{}: command not found", argv[0]),
+        _ => format!("{}: {}", argv[0], e)
+    })
+}
+
+// runs a pipeline with stdin and stdout pointing at the given fds, returns
+// should_continue and the exit status of the last command
+fn execute_pipeline(cfg: &mut config::Config, pipeline: &parser::Pipeline, stdin: RawFd, stdout: RawFd) -> Result<(i32, i32), String> {
+    // a lone command runs in the shell itself so that builtins like cd and exit work
+    if pipeline.commands.len() == 1 {
+        return execute_command(cfg, &pipeline.commands[0], stdin, stdout);
+    }
+
+    let mut running = Vec::new();
+    let mut input = stdin;
+
+    for (idx, command) in pipeline.commands.iter().enumerate() {
+        let (next_input, output) = if idx == pipeline.commands.len() - 1 {
+            (None, stdout)
         }
-        
-        log::debug(cfg, format!("cur_y: {}\ncur_x: {}", cur_y, cur_x).as_str());
-        
-        cur_y -= cols_to_erase as u16;
-        
-        write!(stdout, "{}", cursor::Goto((prompt_len + 1) as u16, cur_y)).unwrap();
-        write!(stdout, "{}", termion::clear::AfterCursor).unwrap();
-        write!(stdout, "{}", line).unwrap();
-        
-        if cols_to_erase > 0 {
-            cur_y += cols_to_erase as u16;
+
+        else {
+            let (fd_read, fd_write) = nix::unistd::pipe2(OFlag::O_CLOEXEC).map_err(|e| format!("pipe: {}", e))?;
+            (Some(fd_read), fd_write)
+        };
+
+        // every command of a pipeline runs in its own copy of the shell, side by side
+        let pid = fork_shell(cfg, input, output, next_input, |cfg| match execute_command(cfg, command, 0, 1) {
+            Ok((_, status)) => status,
+            Err(e) => {
+                eprintln!("rsh: {}", e);
+                1
+            }
+        });
+
+        if input != stdin {
+            nix::unistd::close(input).ok();
         }
-        
-        log::debug(cfg, format!("prompt_len: {}", prompt_len).as_str());
-        log::debug(cfg, format!("if cond: {:?}", (line.len() + prompt_len) % terminal_cols as usize == 0).as_str());
 
-        if cur_y - 1 as u16 == terminal_lines && (line.len() + prompt_len) % terminal_cols as usize == 0 {
-            log::debug(cfg, "writting newline to stdout");
-            write!(stdout, "\n").unwrap();
+        if output != stdout {
+            nix::unistd::close(output).ok();
         }
-        
-        write!(stdout, "{}", cursor::Goto(cur_x, cur_y)).unwrap();
-        stdout.flush().unwrap();
-    }
 
-    write!(stdout, "\r\n").unwrap();
-    line
-}
+        match pid {
+            Ok(pid) => running.push(Running::Forked(pid)),
+            Err(e) => eprintln!("rsh: {}", e)
+        }
 
-fn read_command(cfg: &config::Config) -> String {
-    let mut line = read_raw(cfg);
-    
-    line = line.trim().to_string();
-    
-    log::debug(cfg, format!("line: {}", line).as_str());
-    
-    if line.is_empty() {
-        return line;
+        input = next_input.unwrap_or(stdin);
     }
-    
-    while line.chars().nth(line.len() - 1).unwrap() == '\\' {
-        line.remove(line.len() - 1);
-        
-        print_prompt2(&cfg);
-        
-        let ap_line = read_raw(cfg);
-            
-        line.push_str(ap_line.as_str());
-        
-        line.remove(line.len()- 1);
+
+    let mut status = 1;
+
+    for command in running {
+        status = wait_for(command);
     }
 
-    line
+    Ok((1, status))
 }
 
-fn execute_builtin(argv : &mut Vec<tree::TreeNode<Box<parser::Token>>>, cfg: &mut config::Config) -> Result<(i32, i32, i32), String> {
-    let builtin = cfg.rsh_builtins.get(&*argv[0].value.value).unwrap();
-    Ok(builtin(&argv[1..].iter().map(|arg| &*arg.value.value).collect::<Vec<&String>>(), cfg).unwrap())
-}
+// runs a pipeline as part of a list, an error counts as status 1. ! inverts the
+// status and $? is set after every pipeline so that the next one sees it
+fn run_pipeline(cfg: &mut config::Config, pipeline: &parser::Pipeline, stdin: RawFd, stdout: RawFd) -> (i32, i32) {
+    let (should_continue, mut status) = match execute_pipeline(cfg, pipeline, stdin, stdout) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("rsh: {}", e);
+            (1, 1)
+        }
+    };
 
-fn execute_pipeline(cfg: &mut config::Config, pipeline: &mut Vec<tree::TreeNode<Box<parser::Token>>>, stdin: i32, stdout: i32) -> Result<(i32, i32, i32), String> {
-    log::debug(cfg, format!("executing pipe:\n {}\n\npiped into:\n\n{}\n", pipeline[0].value.value, pipeline[1].value.value).as_str());
-    
-    let (fd_read, fd_write) = nix::unistd::pipe().unwrap();
-    let mut _should_continue = 1; 
-    let should_continue;
-    let status ;
-    let mut _status = 0;
-    let mut stdout_ = io::stdout().as_raw_fd();
-    let stdout_ret;
-    
-    (_should_continue, _status, stdout_) = execute_command(cfg,&mut pipeline[0], parser::TokenType::PipelineRedirect, stdin, fd_write).unwrap();
-    
-    (should_continue, status, stdout_ret) = execute_command(cfg, &mut pipeline[1], parser::TokenType::PipelineRedirect, fd_read, stdout).unwrap();
-    
-    
-    Ok((should_continue, status, stdout_ret))
+    if pipeline.negated {
+        status = (status == 0) as i32;
+    }
+
+    symbol_table::set_env_var("?", &status.to_string(), cfg);
+    (should_continue, status)
 }
 
-fn execute_out_redir(cfg: &mut config::Config, command: &mut tree::TreeNode<Box<parser::Token>>, stdin: i32) -> Result<(i32, i32, i32), String> {
-    // file needs to live until stdout redirection
-    // so that fd is still valid
-   
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(command.children[1].value.value.as_str())
-        .unwrap();
+// && runs the next pipeline only after a success and || only after a failure
+fn execute_and_or(cfg: &mut config::Config, and_or: &parser::AndOr, stdin: RawFd, stdout: RawFd) -> (i32, i32) {
+    let (mut should_continue, mut status) = run_pipeline(cfg, &and_or.first, stdin, stdout);
+
+    for (connector, pipeline) in and_or.rest.iter() {
+        if should_continue != 1 {
+            break;
+        }
+
+        let run = match connector {
+            parser::Connector::And => status == 0,
+            parser::Connector::Or => status != 0
+        };
+
+        if run {
+            (should_continue, status) = run_pipeline(cfg, pipeline, stdin, stdout);
+        }
+    }
 
-    let fd = file.as_raw_fd();
-    execute_command(cfg, &mut command.children[0], parser::TokenType::Node, stdin, fd)
+    (should_continue, status)
 }
 
-fn execute_out_app(cfg: &mut config::Config, command: &mut tree::TreeNode<Box<parser::Token>>, stdin: i32) -> Result<(i32, i32, i32), String> {
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .append(true)
-        .open(command.children[1].value.value.as_str())
-        .unwrap();
+fn execute_list(cfg: &mut config::Config, list: &parser::List, stdin: RawFd, stdout: RawFd) -> (i32, i32) {
+    let mut should_continue = 1;
+    let mut status = 0;
+
+    for and_or in list.items.iter() {
+        (should_continue, status) = execute_and_or(cfg, and_or, stdin, stdout);
+
+        if should_continue != 1 {
+            break;
+        }
+    }
 
-    let fd = file.as_raw_fd();
-    execute_command(cfg, &mut command.children[0], parser::TokenType::Node, stdin, fd)
+    (should_continue, status)
 }
 
-fn execute_command(cfg: &mut config::Config, parsed_command: &mut tree::TreeNode<Box<parser::Token>>, t_type: parser::TokenType, stdin: i32, stdout: i32) -> Result<(i32, i32, i32), String> {
-    log::debug(cfg, format!("\nexecuting {:?}\n", parsed_command.children.iter().map(|child| &*child.value.value).collect::<Vec<&String>>()).as_str());
+fn execute_command(cfg: &mut config::Config, command: &parser::Command, stdin: RawFd, stdout: RawFd) -> Result<(i32, i32), String> {
+    log::debug(cfg, format!("\nexecuting {:?}\n", command).as_str());
     log::debug(cfg, format!("\nstdin: {:?}\nstdout: {:?}\n", stdin, stdout).as_str());
-    log::debug(cfg, format!("\nview into tree: {:#?}\n", parsed_command).as_str());
-    
-    let mut should_continue: i32 = 1;
-    let mut status: i32 = 0;
-    let mut child_stdout: i32 = io::stdout().as_raw_fd();
-    
-    // assume it is a compound command
-    let compound_command = &mut parsed_command.children;
 
-    for child in compound_command.iter() {
-        log::debug(cfg, format!("{}, {:?}", child.value.value, child.value.t_type).as_str());
+    let mut fds = Vec::new();
+    let mut files = Vec::new();
+
+    if stdin != 0 {
+        fds.push((0, stdin));
     }
-    
-    for child in compound_command.iter_mut() {
-        (should_continue, status, child_stdout) = match child.value.t_type  {
-            parser::TokenType::Subshell => execute_command(cfg, child, parser::TokenType::Subshell, stdin, stdout).unwrap(),
-            parser::TokenType::OutputRedirect => execute_out_redir(cfg, child, stdin).unwrap(),
-            parser::TokenType::PipelineRedirect => execute_pipeline(cfg, &mut child.children, stdin, stdout).unwrap(),
-            parser::TokenType::OutputRedirectAppend => execute_out_app(cfg, child, stdin).unwrap(),
-            parser::TokenType::Word => continue,
-            parser::TokenType::PipelineSendOuput => execute_command(cfg, child, parser::TokenType::PipelineSendOuput, stdin, stdout).unwrap(),
-            parser::TokenType::PipelineGetInput => execute_command(cfg, child, parser::TokenType::PipelineGetInput, stdin, stdout).unwrap(),
-            parser::TokenType::QuotedStr => execute_command(cfg, child, parser::TokenType::QuotedStr, stdin, stdout).unwrap(),
-            parser::TokenType::Node => execute_command(cfg, child, parser::TokenType::Node, stdin, stdout).unwrap()
-                
-        }
+
+    if stdout != 1 {
+        fds.push((1, stdout));
     }
-    
-    
-    if compound_command.len() < 1 {
-        return Ok((1, 0, stdout));
+
+    let simple = match command {
+        parser::Command::Simple(simple) => simple,
+        parser::Command::Subshell(list, redirects) => {
+            open_redirects(cfg, redirects, &mut fds, &mut files)?;
+
+            let pid = with_fds(&fds, || fork_shell(cfg, 0, 1, None, |cfg| execute_list(cfg, list, 0, 1).1))?;
+
+            return Ok((1, wait_for(Running::Forked(pid))));
+        }
+    };
+
+    let mut assignments = Vec::new();
+
+    for assignment in simple.assignments.iter() {
+        let (name, value) = assignment.text.split_once('=').unwrap();
+        assignments.push((name.to_string(), expand::expand_word(cfg, value)?));
     }
-    
-    if matches!(compound_command[0].value.t_type, parser::TokenType::Word) {
-        log::debug(cfg, format!("{:?}", compound_command.iter().map(|v| &*v.value.value).collect::<Vec<&String>>()).as_str());
-        
-        if matches!(compound_command[0].value.w_type, parser::WordType::Builtin) {
-            (should_continue, status, child_stdout) = execute_builtin(compound_command, cfg).unwrap();
+
+    let argv = expand::expand_words(cfg, &simple.words)?;
+    open_redirects(cfg, &simple.redirects, &mut fds, &mut files)?;
+
+    // NAME=value on its own sets a shell variable
+    if argv.is_empty() {
+        for (name, value) in assignments {
+            cfg.variables.insert(name, value);
         }
-        
-        else {
-            let mut command = Command::new(&*compound_command[0].value.value);
-            let mut command = command
-                .args(&compound_command[1..].iter().map(|v| &*v.value.value).collect::<Vec<&String>>());
 
-            if stdout != io::stdout().as_raw_fd() && !matches!(parsed_command.value.t_type, parser::TokenType::Subshell){
-                command = command.stdout(Stdio::from( unsafe {File::from_raw_fd(stdout) } ));
-            }
+        return Ok((1, 0));
+    }
+
+    let args = argv[1..].iter().collect::<Vec<&String>>();
+
+    match parser::classify_word(cfg, &argv[0]) {
+        parser::WordType::Builtin => {
+            let builtin = cfg.rsh_builtins[&argv[0]];
 
-            if stdin != io::stdin().as_raw_fd() && !matches!(parsed_command.value.t_type, parser::TokenType::Subshell) {
-                command = command.stdin(Stdio::from(unsafe { File::from_raw_fd(stdin) } ));
+            match with_fds(&fds, || builtin(&args, cfg)) {
+                Ok((should_continue, status, _)) => Ok((should_continue, status)),
+                Err(e) => {
+                    eprintln!("rsh: {}: {}", argv[0], e);
+                    Ok((1, 1))
+                }
             }
+        },
+        parser::WordType::Function => {
+            let body = cfg.functions[&argv[0]].clone();
+            Ok(with_fds(&fds, || execute_line(cfg, &body)))
+        },
+        parser::WordType::Keyword => {
+            let keyword = cfg.keywords[&argv[0]];
+            let (should_continue, status) = with_fds(&fds, || keyword(&args, cfg))?;
 
-            
-            
-            log::debug(cfg, format!("{:?}", parsed_command.value.t_type).as_str());
-            
-            // parsed_command is either a simple Node, which is a vec of command and args
-            // or it is just a single-word command which will be classified as a word
-            
-            if !matches!(t_type, parser::TokenType::QuotedStr) &&
-                (matches!(parsed_command.value.t_type, parser::TokenType::Node) ||
-                 matches!(parsed_command.value.t_type, parser::TokenType::Word) || 
-                 matches!(parsed_command.value.t_type, parser::TokenType::PipelineGetInput) ||
-                 matches!(parsed_command.value.t_type, parser::TokenType::PipelineSendOuput)) {
-                    
-                let child = command.spawn();
-
-                match child {
-                    Ok(_) => {},
-                    Err(e) => {
-                        println!("Error: {}", e);
-                        return Ok((1, 127, stdout));
-                    }
-                };
+            Ok((should_continue, status))
+        },
+        parser::WordType::General => {
+            let child = match spawn_external(&argv, &assignments, &fds) {
+                Ok(child) => child,
+                Err(e) => {
+                    eprintln!("rsh: {}", e);
+                    return Ok((1, 127));
+                }
+            };
+
+            // the files can be closed once the child has its own copies
+            drop(files);
+
+            Ok((1, wait_for(Running::Child(child))))
+        }
+    }
+}
+
+
+// reads a byte at a time so whatever comes after the line is still there for the
+// commands that read from the same fd, None once there is nothing left
+fn read_line_from(file: &mut File) -> io::Result<Option<String>> {
+    let mut bytes = Vec::new();
+    let mut byte = [0u8; 1];
+
+    loop {
+        match file.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) => {
+                if byte[0] == b'\n' {
+                    return Ok(Some(String::from_utf8_lossy(&bytes).to_string()));
+                }
+
+                bytes.push(byte[0]);
+            },
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        }
+    }
+
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(String::from_utf8_lossy(&bytes).to_string()))
+}
+
+// runs every line read from fd without prompts or the line editor, like sh -s does,
+// returns whether to keep going and the status of the last command. source names
+// where the lines come from in syntax errors
+fn run_lines(cfg: &mut config::Config, fd: RawFd, source: &str) -> (i32, i32) {
+    let mut should_continue = 1;
+    let mut status = 0;
+    let mut line_number = 0;
 
-                let mut child = child.unwrap();
-                
-                status = child.wait().unwrap().code().unwrap();
+    // stdin has to stay open, the commands being run share it
+    let mut file = std::mem::ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+
+    loop {
+        let line = match read_line_from(&mut file) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                log::debug(cfg, format!("could not read line: {}", e).as_str());
+                break;
             }
+        };
+
+        line_number += 1;
+        let first_line = line_number;
+
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
 
-            else if matches!(parsed_command.value.t_type, parser::TokenType::Subshell) {
+        let mut line = line;
 
-                
-                let child_output = command.output().expect("Failed to retrieve output from command");
-                parsed_command.value.value = Box::new(String::from_utf8_lossy(child_output.stdout.as_slice()).trim().to_string());
-                
-                log::debug(cfg, format!("parsed_command.value.value = {}", parsed_command.value.value).as_str());
-                
-                status = child_output.status.code().unwrap();
+        // an open quote, a trailing | and so on continue on the next line
+        while parser::is_incomplete(&line) {
+            match read_line_from(&mut file) {
+                Ok(Some(next)) => {
+                    line_number += 1;
+                    line.push('\n');
+                    line.push_str(&next);
+                },
+                _ => break
             }
-            
-            symbol_table::set_env_var("?", &status.to_string(), cfg);
+        }
+
+        (should_continue, status) = execute_source(cfg, &line, source, Some(first_line));
+
+        if should_continue != 1 {
+            break;
         }
     }
 
-    
-    if matches!(t_type, parser::TokenType::QuotedStr) {
-        parsed_command.value.value = Box::new(String::from(parsed_command.children.iter().map(|child| child.value.value.to_string()).collect::<Vec<String>>().join("")));
+    if fd != io::stdin().as_raw_fd() {
+        unsafe { std::mem::ManuallyDrop::drop(&mut file) };
     }
-    
-    return Ok((should_continue, status, child_stdout));
+
+    (should_continue, status)
 }
 
+// how many Ctrl-Ds in a row are ignored before the shell exits, IGNOREEOF takes
+// precedence over set -o ignoreeof and anything that isn't a number means 10 like in bash
+fn ignored_eofs(cfg: &config::Config) -> usize {
+    match cfg.variables.get("IGNOREEOF") {
+        Some(count) => count.parse::<usize>().unwrap_or(10),
+        None if config::option_enabled(cfg, "ignoreeof") => 10,
+        None => 0
+    }
+}
 
 fn main_loop(cfg: &mut config::Config) -> i32 {
-    let mut should_continue = 1;
-    let mut status = 0;
-    let mut _stdout = io::stdout().as_raw_fd();
+    let mut should_continue;
+    let mut status;
 
     if cfg.stdin_to_execute != io::stdin().as_raw_fd() {
-        let file = unsafe { File::from_raw_fd(cfg.stdin_to_execute) };
-        let reader = std::io::BufReader::new(file);
-        let mut lines = reader.lines();
-        
-        while let Some(line) = lines.next() {
-            let mut line = line.unwrap();
+        let script = cfg.variables.get("0").cloned().unwrap_or(String::from("rsh"));
 
-            let mut parsed_command = parser::build_ast(&mut line, &cfg);
+        (_, status) = run_lines(cfg, cfg.stdin_to_execute, &script);
+        return status;
+    }
 
-            (should_continue, status, _stdout) = execute_command(cfg, &mut parsed_command, parser::TokenType::Node, cfg.stdin_to_execute, io::stdout().as_raw_fd()).unwrap();
-            
-            if should_continue != 1 {
-                return status;
-            }
-        }
+    // piped in or running under CI, no terminal to draw an editor on
+    if !termion::is_tty(&io::stdin()) || !termion::is_tty(&io::stdout()) {
+        log::debug(cfg, "stdin or stdout is not a terminal, reading commands line by line");
+
+        (_, status) = run_lines(cfg, io::stdin().as_raw_fd(), "rsh");
+        return status;
     }
+
+    (should_continue, status) = config::load_rshconfig(cfg);
+
+    if should_continue == 0 {
+        return status;
+    }
+    
+    history::load_history(cfg);
+
+    // Ctrl-Ds in a row so far, ignoreeof makes the shell wait for more than one
+    let mut eofs = 0;
     
     while should_continue != 0 {
-            
-        print_prompt1(&cfg);
+        let line = match read_command(cfg) {
+            Some(line) => line,
+            None => {
+                eofs += 1;
+
+                if eofs <= ignored_eofs(cfg) {
+                    println!("Use \"exit\" to leave the shell.");
+                    continue;
+                }
+
+                // bash warns about stopped jobs first, without job control nothing
+                // can be stopped
+                println!("exit");
+                break;
+            }
+        };
 
-        let mut line = read_command(&cfg);
+        eofs = 0;
         
         if line == "" {
             continue;
         }
-        
-        let mut parsed_command = parser::build_ast(&mut line, &cfg);
-        
-        (should_continue, status, _stdout) = execute_command(cfg, &mut parsed_command, parser::TokenType::Node, io::stdin().as_raw_fd(), io::stdout().as_raw_fd()).unwrap();
 
-        log::debug(cfg, format!("\nafter changes in tree:\n\n{:#?}\n", parsed_command).as_str());
+        history::add_entry(cfg, &line);
+
+        (should_continue, status) = execute_line(cfg, &line);
+        history::finish_entry(cfg, &line, status);
     }
 
     return status;
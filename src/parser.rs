@@ -1,275 +1,667 @@
-use crate::tree;
 use crate::config;
-use regex::Regex;
+use crate::graphemes;
+use crate::lexer::{self, Token, TokenType};
 use crate::log;
 
-#[derive(Debug, Clone)]
-pub enum TokenType {
-    Word,
-    Node,
-    PipelineRedirect,
-    PipelineSendOuput,
-    PipelineGetInput,
-    OutputRedirect,
-    OutputRedirectAppend,
-    QuotedStr,
-    Subshell
-}
-
 #[derive(Debug, Clone)]
 pub enum WordType {
-    NotWord,
     Builtin,
     Function,
     Keyword,
     General
 }
 
+// what a command name refers to, the same lookup the shell does when it runs it
+pub fn classify_word(cfg: &config::Config, word: &str) -> WordType {
+    if cfg.rsh_builtins.contains_key(word) {
+        WordType::Builtin
+    }
+
+    else if cfg.functions.contains_key(word) {
+        WordType::Function
+    }
+
+    else if cfg.keywords.contains_key(word) {
+        WordType::Keyword
+    }
+
+    else {
+        WordType::General
+    }
+}
+
+// a word as it was typed, it only gets expanded right before it's used
 #[derive(Debug, Clone)]
-pub struct Token {
-    pub t_type: TokenType,
-    pub w_type: WordType,
-    pub value:  Box<String>,
+pub struct Word {
+    pub text: String,
 }
 
-fn classify_token(s: &String, cfg: &config::Config) -> Token {
-    let mut tok = Token {
-        t_type: TokenType::Word,
-        w_type: WordType::NotWord,
-        value: Box::new(s.to_string()),
-    };
-    
-    match s.as_str() {
-        "|" => tok.t_type = TokenType::PipelineRedirect,
-        ">" => tok.t_type = TokenType::OutputRedirect,
-        ">>" => tok.t_type = TokenType::OutputRedirectAppend,
-        &_ => tok.t_type = TokenType::Word
-    }
-    
-    let re = [
-        // There are still nodes to parse
-        Regex::new(r#"[|><'"$()\s]+"#).unwrap(),
-    ];
-
-    if re[0].is_match(s) && matches!(tok.t_type, TokenType::Word) {
-        tok.t_type = TokenType::Node;
-    }
-    
-
-
-    // Otherwise it's a word
-    if matches!(tok.t_type, TokenType::Word) {
-        match s.as_str() {
-            val if cfg.rsh_builtins.get(val) != None  => tok.w_type = WordType::Builtin,
-            val if cfg.functions.get(val) != None => tok.w_type = WordType::Function,
-            val if cfg.keywords.get(val) != None => tok.w_type = WordType::Keyword,
-            &_ => tok.w_type = WordType::General
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    Input,
+    Output,
+    Append,
+    Clobber,
+    ReadWrite,
+    DupInput,
+    DupOutput,
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    // the fd being redirected, when it's not the default for kind
+    pub fd: Option<i32>,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+impl Redirect {
+    pub fn fd(&self) -> i32 {
+        match self.fd {
+            Some(fd) => fd,
+            None if matches!(self.kind, RedirectKind::Input | RedirectKind::ReadWrite | RedirectKind::DupInput) => 0,
+            None => 1
         }
     }
+}
 
-    tok
+#[derive(Debug, Clone, Default)]
+pub struct SimpleCommand {
+    // NAME=value words in front of the command
+    pub assignments: Vec<Word>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
 }
 
+#[derive(Debug, Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Subshell(Box<List>, Vec<Redirect>),
+}
 
-fn split_simple_command(s: &String, cfg: &config::Config) -> Vec<String> {
-    let mut split_string: Vec<String> = s.split(" ").map(|word| word.to_string()).collect();
-    let mut ret_vec: Vec<String> = Vec::new();
-    
-    for s in split_string.iter_mut() {
-        if s.starts_with('$') {
-            let var_name = &s[1..];
-            let val = cfg.variables.get(var_name);
-            let val = match val {
-                Some(v) => v,
-                None => ""
-            };
-                
-            ret_vec.push(val.to_string());
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    // ! in front of the pipeline inverts its status
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+// pipelines joined by && and ||, whether the pipeline after a connector runs
+// depends on the status of the ones before it
+#[derive(Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+// and-or lists separated by ; or newlines, run one after the other
+#[derive(Debug, Clone)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+// why and where parsing failed, start and end are byte offsets in the input, line
+// and column count from 1
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub expected: Option<String>,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+
+    // the line the error is on with carets under the offending part
+    pub snippet: String,
+}
+
+impl ParseError {
+    fn new(input: &str, start: usize, end: usize, message: String, expected: Option<&str>) -> ParseError {
+        let line_start = input[..start].rfind('\n').map_or(0, |idx| idx + 1);
+        let line_end = input[start..].find('\n').map_or(input.len(), |idx| start + idx);
+
+        let indent = graphemes::display_width(&input[line_start..start]);
+        let width = graphemes::display_width(&input[start..end.clamp(start, line_end)]).max(1);
+
+        ParseError {
+            message,
+            expected: expected.map(String::from),
+            start,
+            end,
+            line: input[..start].matches('\n').count() + 1,
+            column: input[line_start..start].chars().count() + 1,
+            snippet: format!("{}\n{}{}", &input[line_start..line_end], " ".repeat(indent), "^".repeat(width)),
         }
-       
-        else {
-            ret_vec.push(s.to_string())
+    }
+
+    // the error the way it's printed, source is the shell or the script it's from and
+    // first_line the line of the script the input starts on
+    pub fn render(&self, source: &str, first_line: Option<usize>) -> String {
+        let mut message = self.message.clone();
+
+        if let Some(expected) = &self.expected {
+            message = format!("{}, expected {}", message, expected);
         }
 
+        let header = match first_line {
+            Some(first_line) => format!("{}: line {}, column {}: {}", source, first_line + self.line - 1, self.column, message),
+            None if self.line > 1 => format!("{}: line {}, column {}: {}", source, self.line, self.column, message),
+            None => format!("{}: {}", source, message)
+        };
+
+        format!("{}\n{}", header, self.snippet)
     }
+}
 
-    ret_vec
+struct Parser<'a> {
+    input: &'a str,
+    tokens: &'a [Token],
+    pos: usize,
 }
 
-pub fn parse_quot_string(s: &str, cfg: &config::Config) -> Vec<tree::TreeNode<Box<Token>>> {
-    let re = vec![
-        Regex::new(r#"(?P<before>.*)\$\((?P<sub>.*)\)(?P<after>.*)"#).unwrap(),
-    ];
-
-    let mut children: Vec::<tree::TreeNode<Box<Token>>> = Vec::new();
-    
-    if re[0].is_match(s) {
-        let captures = re[0].captures(s).unwrap();
-        
-        let bef_str = captures.name("before").unwrap().as_str();
-        log::debug(cfg, format!("{}", bef_str).as_str());
-        children.extend(parse_quot_string(bef_str, cfg));
-        
-        let sub = captures.name("sub").unwrap().as_str().to_string();
-        let mut subtree = build_ast(&sub, cfg);
-        subtree.value.t_type = TokenType::Subshell;
-        children.push(*subtree);
-
-        let aft_str = captures.name("after").unwrap().as_str();
-        log::debug(cfg, format!("{}", aft_str).as_str());
-        children.extend(parse_quot_string(aft_str, cfg));
-        
-    }
-    
-    vec![
-        tree::TreeNode {
-        value: Box::new(Token {
-            t_type: TokenType::QuotedStr,
-            w_type: WordType::NotWord,
-            value: Box::new(s.to_string())
-        }),
-        
-        children
-        }
-    ]
+// whether word is NAME=value, NAME being a valid variable name
+pub fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => is_name(name),
+        None => false
+    }
 }
 
-pub fn build_ast (command: &String, cfg: &config::Config)
-    ->  Box<tree::TreeNode<Box<Token>>> {
-    log::debug(cfg, format!("building ast for {}", command).as_str());
-    
-    let mut root = Box::new(tree::TreeNode {
-         value: Box::new(classify_token(command, cfg)),
-        children: Vec::new(),
-    });
-
-    
-    let regexp = vec![
-        // Pipeline
-        Regex::new(r#"^(?P<left>.*)\|(?P<right>.*)$"#).unwrap(),
-        
-        // Single-quoted or double quoted string
-        Regex::new(r#"^(?P<before>.*)["'](?P<str>.*)["'](?P<after>.*)$"#).unwrap(),
-        
-        // $() or () syntax
-        Regex::new(r"^(?P<before>.*)\$\((?P<sub>.*)\)(?P<after>.*)$").unwrap(),
-
-        // Output redirect (>)
-        Regex::new(r"^(?P<before>.*)[^>]>[^>](?P<after>.*)$").unwrap(),
-
-        // Output append (>>)
-        Regex::new(r"^(?P<before>.*)>>(?P<after>.*)$").unwrap(),
-    ];
-    
-
-    if regexp[0].is_match(command) {
-        let mut left = build_ast(&regexp[0].captures(command).unwrap().name("left").unwrap().as_str().trim().to_string(), cfg);
-        left.value.t_type = TokenType::PipelineSendOuput;
-        
-        let mut right = build_ast(&regexp[0].captures(command).unwrap().name("right").unwrap().as_str().trim().to_string(), cfg);
-        right.value.t_type = TokenType::PipelineGetInput;
-        
-        root.children.push(tree::TreeNode {
-            value: Box::new(classify_token(&String::from("|"), cfg)),
-            children: Vec::from(vec![
-                *left,
-                *right
-            ])
-        });
-    }
-
-    else if regexp[1].is_match(command) {
-        let captures = regexp[1].captures(command).unwrap();
-       
-        let bef_str = captures.name("before").unwrap().as_str().trim().to_string();
-        let aft_str = captures.name("after").unwrap().as_str().trim().to_string();
-        
-        if bef_str != "" {
-            root.children.extend(build_ast(&bef_str, cfg).children);
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_'),
+        _ => false
+    }
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_type(&self) -> Option<TokenType> {
+        self.peek().map(|token| token.t_type)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, start: usize, end: usize, message: &str, expected: Option<&str>) -> ParseError {
+        ParseError::new(self.input, start, end, message.to_string(), expected)
+    }
+
+    // the next token isn't one of what was expected there
+    fn unexpected(&self, expected: &str) -> ParseError {
+        let message = match self.peek() {
+            Some(token) if token.t_type == TokenType::Newline => String::from("syntax error near unexpected token `newline'"),
+            Some(token) => format!("syntax error near unexpected token `{}'", token.text),
+            None => String::from("syntax error: unexpected end of input")
+        };
+
+        let (start, end) = match self.peek() {
+            Some(token) => (token.start, token.end),
+            None => (self.input.len(), self.input.len())
+        };
+
+        self.error(start, end, &message, Some(expected))
+    }
+
+    // a construct the shell doesn't run, reported at the next token
+    fn unsupported(&self, message: &str) -> ParseError {
+        let token = self.peek().unwrap();
+        self.error(token.start, token.end, message, None)
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek_type() == Some(TokenType::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    // program: linebreak [list] linebreak
+    fn parse_program(&mut self) -> Result<Option<List>, ParseError> {
+        let list = self.parse_list()?;
+
+        if self.peek().is_some() {
+            return Err(self.unexpected("the end of the command"));
+        }
+
+        if list.items.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(list))
+    }
+
+    // list: linebreak and_or ((';' | newline) linebreak and_or)* [';'] linebreak, stops
+    // at whatever can't start a command, like the ) that closes a subshell
+    fn parse_list(&mut self) -> Result<List, ParseError> {
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_newlines();
+
+            if matches!(self.peek_type(), None | Some(TokenType::RParen)) {
+                break;
+            }
+
+            items.push(self.parse_and_or()?);
+
+            match self.peek_type() {
+                Some(TokenType::Semi) | Some(TokenType::Newline) => {
+                    self.next();
+                },
+                Some(TokenType::Amp) => return Err(self.unsupported("background jobs are not supported")),
+                _ => break
+            }
+        }
+
+        Ok(List { items })
+    }
+
+    // and_or: pipeline (('&&' | '||') linebreak pipeline)*
+    fn parse_and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+
+        loop {
+            let connector = match self.peek_type() {
+                Some(TokenType::AndIf) => Connector::And,
+                Some(TokenType::OrIf) => Connector::Or,
+                _ => break
+            };
+
+            self.next();
+            self.skip_newlines();
+
+            rest.push((connector, self.parse_pipeline()?));
+        }
+
+        Ok(AndOr { first, rest })
+    }
+
+    // pipeline: ['!'] command ('|' linebreak command)*
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let negated = self.peek().is_some_and(|token| token.t_type == TokenType::Word && token.text == "!");
+
+        if negated {
+            self.next();
         }
-        
-        let quot_str = parse_quot_string(&captures.name("str").unwrap().as_str().trim().to_string(), cfg);
-        root.children.push(quot_str.get(0).unwrap().clone());
 
-        if aft_str != "" {
-            root.children.extend(build_ast(&aft_str, cfg).children);
+        let mut commands = vec![self.parse_command()?];
+
+        while self.peek_type() == Some(TokenType::Pipe) {
+            self.next();
+            self.skip_newlines();
+
+            commands.push(self.parse_command()?);
         }
+
+        Ok(Pipeline { negated, commands })
     }
-    
-    else if regexp[2].is_match(command) {
-        let captures = regexp[2].captures(command).unwrap();
-        
-        let bef_str = &captures.name("before").unwrap().as_str().trim().to_string();
-        
-        if bef_str != "" {
-            root.children.extend(build_ast(bef_str, cfg).children);
+
+    // command: '(' list ')' redirect* | simple_command
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        if self.peek_type() != Some(TokenType::LParen) {
+            return Ok(Command::Simple(self.parse_simple_command()?));
+        }
+
+        self.next();
+
+        let list = self.parse_list()?;
+
+        if list.items.is_empty() {
+            return Err(self.unexpected("a command"));
         }
-        
-        let mut subcommand = build_ast(&captures.name("sub").unwrap().as_str().trim().to_string(), cfg);
-        subcommand.value.t_type = TokenType::Subshell;
-        root.children.push(*subcommand);
-
-        let aft_str = &captures.name("after").unwrap().as_str().trim().to_string();
-        if aft_str != "" {
-            root.children.extend(build_ast(aft_str, cfg).children);
+
+        if self.peek_type() != Some(TokenType::RParen) {
+            return Err(self.unexpected("`)'"));
+        }
+
+        self.next();
+
+        let mut redirects = Vec::new();
+
+        while self.at_redirect() {
+            redirects.push(self.parse_redirect()?);
+        }
+
+        Ok(Command::Subshell(Box::new(list), redirects))
+    }
+
+    fn at_redirect(&self) -> bool {
+        match self.peek_type() {
+            Some(TokenType::IoNumber) => true,
+            Some(t_type) => lexer::is_redirection(t_type),
+            None => false
         }
     }
 
-    else if regexp[3].is_match(command) {
-        let captures = regexp[3].captures(command).unwrap();
+    // simple_command: (assignment | redirect)* (word | redirect)*, with at least one of them
+    fn parse_simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+
+        loop {
+            if self.at_redirect() {
+                command.redirects.push(self.parse_redirect()?);
+                continue;
+            }
 
-        let bef_str = &captures.name("before").unwrap().as_str().trim().to_string();
-        let aft_str = &captures.name("after").unwrap().as_str().trim().to_string();
+            let token = match self.peek() {
+                Some(token) if token.t_type == TokenType::Word => token,
+                _ => break
+            };
+
+            let word = Word { text: token.text.clone() };
+            self.next();
+
+            if command.words.is_empty() && is_assignment(&word.text) {
+                command.assignments.push(word);
+            }
+
+            else {
+                command.words.push(word);
+            }
+        }
+
+        if command.assignments.is_empty() && command.words.is_empty() && command.redirects.is_empty() {
+            return Err(self.unexpected("a command"));
+        }
+
+        Ok(command)
+    }
+
+    // redirect: [io_number] operator word
+    fn parse_redirect(&mut self) -> Result<Redirect, ParseError> {
+        let mut fd = None;
+
+        if self.peek_type() == Some(TokenType::IoNumber) {
+            fd = self.next().unwrap().text.parse::<i32>().ok();
+        }
+
+        let kind = match self.peek_type() {
+            Some(TokenType::Less) => RedirectKind::Input,
+            Some(TokenType::Great) => RedirectKind::Output,
+            Some(TokenType::DGreat) => RedirectKind::Append,
+            Some(TokenType::Clobber) => RedirectKind::Clobber,
+            Some(TokenType::LessGreat) => RedirectKind::ReadWrite,
+            Some(TokenType::LessAnd) => RedirectKind::DupInput,
+            Some(TokenType::GreatAnd) => RedirectKind::DupOutput,
+            Some(TokenType::DLess) | Some(TokenType::DLessDash) => return Err(self.unsupported("here-documents are not supported")),
+            _ => return Err(self.unexpected("a redirection operator"))
+        };
+
+        self.next();
+
+        let target = match self.peek() {
+            Some(token) if token.t_type == TokenType::Word => Word { text: token.text.clone() },
+            _ => return Err(self.unexpected("a file name"))
+        };
+
+        self.next();
+
+        Ok(Redirect { fd, kind, target })
+    }
+}
+
+// parses a whole command line, None when there's nothing in it to run
+pub fn build_ast(command: &str, cfg: &config::Config) -> Result<Option<List>, ParseError> {
+    log::debug(cfg, format!("building ast for {}", command).as_str());
+
+    let tokens = match lexer::tokenize(command) {
+        Ok(tokens) => tokens,
+        Err((pos, message)) => return Err(ParseError::new(command, pos, pos + 1, message, None))
+    };
+
+    let mut parser = Parser { input: command, tokens: &tokens, pos: 0 };
+
+    let ast = parser.parse_program()?;
+    log::debug(cfg, format!("ast: {:#?}", ast).as_str());
+
+    Ok(ast)
+}
 
-        let mut out_redir_tree = Vec::new();
-        
-        if bef_str != "" {
-            out_redir_tree.push(*build_ast(bef_str, cfg));
+// checks a word against the reserved words that open or close a block, only when
+// it's where a command would go
+fn check_reserved_word(word: &str, open: &mut Vec<&str>, command_position: &mut bool) {
+    if *command_position {
+        match word {
+            "if" => open.push("if"),
+            "case" => open.push("case"),
+            "for" | "while" | "until" => open.push("loop"),
+            "{" => open.push("{"),
+            "fi" | "esac" | "done" | "}" => {
+                let opener = match word {
+                    "fi" => "if",
+                    "esac" => "case",
+                    "done" => "loop",
+                    _ => "{"
+                };
+
+                if open.last() == Some(&opener) {
+                    open.pop();
+                }
+            },
+            _ => {}
         }
-        
-        if aft_str != "" {
-            out_redir_tree.push(*build_ast(aft_str, cfg));
+    }
+
+    // most reserved words are followed by another command, for and case by a name
+    *command_position = *command_position && matches!(word, "if" | "then" | "else" | "elif" | "while" | "until" | "do" | "{" | "!");
+}
+
+// whether an if, case, loop or { in tokens is never closed, the parser doesn't know
+// about those blocks
+fn has_open_block(tokens: &[Token]) -> bool {
+    let mut open: Vec<&str> = Vec::new();
+    let mut command_position = true;
+    let mut redirect_target = false;
+
+    for token in tokens {
+        match token.t_type {
+            TokenType::Word if redirect_target => {
+                redirect_target = false;
+            },
+            TokenType::Word => check_reserved_word(&token.text, &mut open, &mut command_position),
+            TokenType::IoNumber => {},
+            // in a case the ) ends a pattern and a command comes next
+            TokenType::RParen => command_position = open.last() == Some(&"case"),
+            t_type if lexer::is_redirection(t_type) => redirect_target = true,
+            _ => command_position = true
         }
-        
-        root.children.push(tree::TreeNode {
-            value: Box::new(classify_token(&String::from(">"), cfg)),
-            children: out_redir_tree
-        });
     }
 
-    else if regexp[4].is_match(command) {
-        let captures = regexp[4].captures(command).unwrap();
+    !open.is_empty()
+}
+
+// whether command can't run yet because something in it is still open, like a quote,
+// a $( or an if without its fi, or because it ends in a |, && or backslash. the line
+// editor starts a continuation line instead of running it
+pub fn is_incomplete(command: &str) -> bool {
+    // every error the lexer has is about input that ended too soon
+    let tokens = match lexer::tokenize(command) {
+        Ok(tokens) => tokens,
+        Err(_) => return true
+    };
+
+    if has_open_block(&tokens) {
+        return true;
+    }
 
-        let bef_str = &captures.name("before").unwrap().as_str().trim().to_string();
-        let aft_str = &captures.name("after").unwrap().as_str().trim().to_string();
+    // with the newline the line ends in once it's entered, so that echo > is an error
+    // about the newline like it is when the line runs
+    let entered = format!("{}\n", command);
+    let tokens = match lexer::tokenize(&entered) {
+        Ok(tokens) => tokens,
+        Err(_) => return true
+    };
 
-        let mut out_redir_tree = Vec::new();
+    match (Parser { input: &entered, tokens: &tokens, pos: 0 }).parse_program() {
+        Err(e) => e.message == "syntax error: unexpected end of input",
+        Ok(_) => false
+    }
+}
 
-        if bef_str != "" {
-            out_redir_tree.push(*build_ast(bef_str, cfg));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // build_ast without the config it only needs for logging
+    fn parse(input: &str) -> Result<Option<List>, ParseError> {
+        let tokens = match lexer::tokenize(input) {
+            Ok(tokens) => tokens,
+            Err((pos, message)) => return Err(ParseError::new(input, pos, pos + 1, message, None))
+        };
+
+        Parser { input, tokens: &tokens, pos: 0 }.parse_program()
+    }
+
+    fn simple(command: &Command) -> &SimpleCommand {
+        match command {
+            Command::Simple(simple) => simple,
+            Command::Subshell(..) => panic!("expected a simple command, got {:?}", command)
         }
+    }
+
+    fn words(command: &Command) -> Vec<&str> {
+        simple(command).words.iter().map(|word| word.text.as_str()).collect()
+    }
+
+    #[test]
+    fn empty_input() {
+        assert!(parse("").unwrap().is_none());
+        assert!(parse("  \n\n # comment").unwrap().is_none());
+    }
+
+    #[test]
+    fn lists_and_pipelines() {
+        let list = parse("a | b \"c|d\"; e && f || ! g\nh").unwrap().unwrap();
+        assert_eq!(list.items.len(), 3);
+
+        let first = &list.items[0].first;
+        assert_eq!(first.commands.len(), 2);
+        assert_eq!(words(&first.commands[1]), vec!["b", "\"c|d\""]);
+
+        let and_or = &list.items[1];
+        assert_eq!(words(&and_or.first.commands[0]), vec!["e"]);
+        assert_eq!(and_or.rest.iter().map(|(connector, _)| *connector).collect::<Vec<_>>(), vec![Connector::And, Connector::Or]);
+        assert!(!and_or.rest[0].1.negated);
+        assert!(and_or.rest[1].1.negated);
+        assert_eq!(words(&and_or.rest[1].1.commands[0]), vec!["g"]);
+    }
 
-        if aft_str != "" {
-            out_redir_tree.push(*build_ast(aft_str, cfg));
+    #[test]
+    fn operators_continue_on_the_next_line() {
+        let list = parse("a |\n b &&\n\n c").unwrap().unwrap();
+
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(list.items[0].first.commands.len(), 2);
+        assert_eq!(list.items[0].rest.len(), 1);
+    }
+
+    #[test]
+    fn assignments_only_before_the_command() {
+        let list = parse("A=1 B=2 cmd C=3").unwrap().unwrap();
+        let command = simple(&list.items[0].first.commands[0]);
+
+        assert_eq!(command.assignments.iter().map(|word| word.text.as_str()).collect::<Vec<_>>(), vec!["A=1", "B=2"]);
+        assert_eq!(words(&list.items[0].first.commands[0]), vec!["cmd", "C=3"]);
+    }
+
+    #[test]
+    fn redirects() {
+        let list = parse("cmd 2>err >out arg <in 2>&1 3<>rw >>log >|clob").unwrap().unwrap();
+        let command = simple(&list.items[0].first.commands[0]);
+
+        assert_eq!(words(&list.items[0].first.commands[0]), vec!["cmd", "arg"]);
+
+        let redirects: Vec<(i32, RedirectKind, &str)> = command.redirects.iter()
+            .map(|redirect| (redirect.fd(), redirect.kind, redirect.target.text.as_str()))
+            .collect();
+
+        assert_eq!(redirects, vec![
+            (2, RedirectKind::Output, "err"),
+            (1, RedirectKind::Output, "out"),
+            (0, RedirectKind::Input, "in"),
+            (2, RedirectKind::DupOutput, "1"),
+            (3, RedirectKind::ReadWrite, "rw"),
+            (1, RedirectKind::Append, "log"),
+            (1, RedirectKind::Clobber, "clob"),
+        ]);
+    }
+
+    #[test]
+    fn subshells() {
+        let list = parse("(a; b | c) 2>/dev/null | d").unwrap().unwrap();
+        let pipeline = &list.items[0].first;
+
+        match &pipeline.commands[0] {
+            Command::Subshell(inner, redirects) => {
+                assert_eq!(inner.items.len(), 2);
+                assert_eq!(redirects.len(), 1);
+                assert_eq!(redirects[0].fd(), 2);
+            },
+            command => panic!("expected a subshell, got {:?}", command)
         }
 
-        root.children.push(tree::TreeNode {
-            value: Box::new(classify_token(&String::from(">>"), cfg)),
-            children: out_redir_tree
-        })
+        assert_eq!(words(&pipeline.commands[1]), vec!["d"]);
     }
-    
-    else {
-        let parsed_command = split_simple_command(command, cfg);
-        
-        for s in parsed_command {
-            let tok = classify_token(&s, cfg);
-            root.children.push(tree::TreeNode{
-                value: Box::new(tok),
-                children: Vec::new()
-            });
+
+    #[test]
+    fn errors() {
+        let error = parse("echo )").unwrap_err();
+        assert_eq!(error.message, "syntax error near unexpected token `)'");
+        assert_eq!(error.expected.as_deref(), Some("the end of the command"));
+        assert_eq!((error.line, error.column), (1, 6));
+        assert_eq!(error.snippet, "echo )\n     ^");
+
+        let error = parse("()").unwrap_err();
+        assert_eq!(error.expected.as_deref(), Some("a command"));
+
+        let error = parse("echo >").unwrap_err();
+        assert_eq!(error.message, "syntax error: unexpected end of input");
+        assert_eq!(error.expected.as_deref(), Some("a file name"));
+
+        let error = parse("a &&\n| b").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 1));
+        assert_eq!(error.render("rsh", Some(10)), "rsh: line 11, column 1: syntax error near unexpected token `|', expected a command\n| b\n^");
+    }
+
+    #[test]
+    fn lexer_errors() {
+        let error = parse("echo \"abc").unwrap_err();
+        assert_eq!(error.start, 5);
+        assert_eq!(error.expected, None);
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(parse("cat <<EOF").unwrap_err().message.contains("here-documents"));
+        assert!(parse("sleep 1 &").unwrap_err().message.contains("background"));
+    }
+
+    #[test]
+    fn incomplete() {
+        for command in ["echo 'a", "echo \"a", "echo $(ls", "echo \"$(ls\"", "a |", "a &&", "a ||", "echo a\\", "if true; then echo", "while true; do", "{ echo", "echo $'a\\'b", "echo ${x", "(echo a", "a &&\n", "case x in"] {
+            assert!(is_incomplete(command), "{:?} should be incomplete", command);
+        }
+    }
+
+    #[test]
+    fn complete() {
+        for command in ["", "echo a", "echo 'a|'", "echo \"$(ls)\"", "a | b", "echo a\\\\", "if true; then echo; fi", "echo if", "echo $'a\\'b'", "echo '\\'", "for x in a; do echo; done", "# if", "echo \\if", "echo (", "echo >", "echo a )", ">if echo"] {
+            assert!(!is_incomplete(command), "{:?} should be complete", command);
         }
     }
-    
-    root
 }
@@ -0,0 +1,175 @@
+use crate::config;
+use crate::log;
+
+use std::env;
+use std::path::Path;
+use nix::unistd;
+
+// what \[ and \] turn into, readline's markers for text that takes up no columns
+pub const START_IGNORE: char = '\x01';
+pub const END_IGNORE: char = '\x02';
+
+// $HOME shown as ~
+fn pretty_path(cfg: &config::Config, path: &str) -> String {
+    match cfg.variables.get("HOME") {
+        Some(home) if !home.is_empty() && (path == home || path.starts_with(format!("{}/", home).as_str())) => {
+            format!("~{}", &path[home.len()..])
+        },
+        _ => path.to_string()
+    }
+}
+
+fn current_dir(cfg: &config::Config) -> String {
+    match env::current_dir() {
+        Ok(dir) => pretty_path(cfg, dir.to_string_lossy().as_ref()),
+        Err(_) => String::from(".")
+    }
+}
+
+fn hostname() -> String {
+    match unistd::gethostname() {
+        Ok(name) => name.to_string_lossy().to_string(),
+        Err(_) => String::from("localhost")
+    }
+}
+
+fn username(cfg: &config::Config) -> String {
+    if let Some(user) = cfg.variables.get("USER") {
+        return user.clone();
+    }
+
+    match unistd::User::from_uid(unistd::geteuid()) {
+        Ok(Some(user)) => user.name,
+        _ => unistd::geteuid().to_string()
+    }
+}
+
+// local time formatted with strftime
+fn local_time(format: &str) -> String {
+    let mut buf = [0u8; 64];
+    let format = format!("{}\0", format);
+
+    let len = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        libc::strftime(buf.as_mut_ptr() as *mut libc::c_char, buf.len(), format.as_ptr() as *const libc::c_char, &tm)
+    };
+
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
+
+// where the $( starting at start is closed, nested parentheses included
+fn substitution_end(ps: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+
+    for (idx, ch) in ps[start + 1..].char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    return Some(start + 1 + idx);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// expands the backslash escapes of a prompt string, then runs the $(...) in it
+pub fn render(cfg: &mut config::Config, ps: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = ps.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            expanded.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('u') => expanded.push_str(username(cfg).as_str()),
+            Some('h') => expanded.push_str(hostname().split('.').next().unwrap_or("")),
+            Some('H') => expanded.push_str(hostname().as_str()),
+            Some('w') => expanded.push_str(current_dir(cfg).as_str()),
+            Some('W') => {
+                let dir = current_dir(cfg);
+
+                if dir == "~" || dir == "/" {
+                    expanded.push_str(dir.as_str());
+                }
+
+                else {
+                    expanded.push_str(Path::new(&dir).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or(dir).as_str());
+                }
+            },
+            Some('$') => expanded.push(if unistd::geteuid().is_root() { '#' } else { '$' }),
+            Some('t') => expanded.push_str(local_time("%H:%M:%S").as_str()),
+            Some('T') => expanded.push_str(local_time("%I:%M:%S").as_str()),
+            Some('A') => expanded.push_str(local_time("%H:%M").as_str()),
+            Some('d') => expanded.push_str(local_time("%a %b %d").as_str()),
+            Some('?') => expanded.push_str(cfg.variables.get("?").map(|status| status.as_str()).unwrap_or("0")),
+            // there's no job control, so never any jobs
+            Some('j') => expanded.push('0'),
+            Some('s') => expanded.push_str("rsh"),
+            Some('n') => expanded.push('\n'),
+            Some('e') => expanded.push('\x1b'),
+            Some('a') => expanded.push('\x07'),
+            Some('[') => expanded.push(START_IGNORE),
+            Some(']') => expanded.push(END_IGNORE),
+            Some('\\') => expanded.push('\\'),
+            Some(digit @ '0'..='7') => {
+                // octal character code, like \033
+                let mut code = digit.to_digit(8).unwrap();
+
+                for _ in 0..2 {
+                    match chars.peek().and_then(|next| next.to_digit(8)) {
+                        Some(next) => {
+                            code = code * 8 + next;
+                            chars.next();
+                        },
+                        None => break
+                    }
+                }
+
+                expanded.push(char::from_u32(code).unwrap_or('?'));
+            },
+            Some(other) => {
+                expanded.push('\\');
+                expanded.push(other);
+            },
+            None => expanded.push('\\')
+        }
+    }
+
+    let mut rendered = String::new();
+    let mut rest = expanded.as_str();
+
+    while let Some(start) = rest.find("$(") {
+        let end = match substitution_end(rest, start) {
+            Some(end) => end,
+            None => break
+        };
+
+        rendered.push_str(&rest[..start]);
+
+        match crate::capture_output_keep_status(cfg, &rest[start + 2..end]) {
+            Ok(output) => rendered.push_str(output.as_str()),
+            Err(e) => log::debug(cfg, format!("prompt substitution failed: {}", e).as_str())
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+// the prompt as it's written to the terminal
+pub fn printable(prompt: &str) -> String {
+    prompt.replace([START_IGNORE, END_IGNORE], "")
+}
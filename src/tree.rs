@@ -1,7 +0,0 @@
-#[derive(Clone, Debug)]
-pub struct TreeNode<T> {
-    pub value: T,
-    pub children: Vec<TreeNode<T>>,
-}
-
-
@@ -0,0 +1,129 @@
+use crate::config;
+use crate::symbol_table;
+
+use std::io::{self, BufReader, Read};
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use termion::input;
+use termion::terminal_size;
+
+// the line editor reads its keys through this, see Input
+pub type Events = input::Events<BufReader<Input>>;
+
+// SIGWINCH writes a byte to this pipe so that a blocked read can notice the resize
+static RESIZE_READ: AtomicI32 = AtomicI32::new(-1);
+static RESIZE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle_sigwinch(_signal: libc::c_int) {
+    let fd = RESIZE_WRITE.load(Ordering::Relaxed);
+
+    if fd >= 0 {
+        unsafe {
+            libc::write(fd, [1u8].as_ptr() as *const libc::c_void, 1);
+        }
+    }
+}
+
+fn watch_resize() -> RawFd {
+    let fd = RESIZE_READ.load(Ordering::Relaxed);
+
+    if fd >= 0 {
+        return fd;
+    }
+
+    let mut fds = [0; 2];
+
+    // neither end may block, the handler can't wait and reads drain whatever is there
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+        return -1;
+    }
+
+    RESIZE_READ.store(fds[0], Ordering::Relaxed);
+    RESIZE_WRITE.store(fds[1], Ordering::Relaxed);
+
+    let action = SigAction::new(SigHandler::Handler(handle_sigwinch), SaFlags::SA_RESTART, SigSet::empty());
+    unsafe { signal::sigaction(Signal::SIGWINCH, &action) }.ok();
+
+    fds[0]
+}
+
+// stdin, except that a read blocked waiting for a key fails with a resize error as
+// soon as the terminal changes size
+pub struct Input {
+    resize_fd: RawFd,
+}
+
+pub fn input() -> Events {
+    input::TermRead::events(BufReader::new(Input { resize_fd: watch_resize() }))
+}
+
+fn resize_error() -> io::Error {
+    io::Error::other("terminal resized")
+}
+
+pub fn is_resize(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::Other && e.to_string() == "terminal resized"
+}
+
+// throws away resizes that happened while nothing was reading, like while a command ran
+pub fn clear_resize() {
+    let fd = RESIZE_READ.load(Ordering::Relaxed);
+    let mut buf = [0u8; 64];
+
+    if fd >= 0 {
+        while unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {}
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut fds = [
+                libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: self.resize_fd, events: libc::POLLIN, revents: 0 },
+            ];
+
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                let e = io::Error::last_os_error();
+
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+
+                return Err(e);
+            }
+
+            if fds[1].revents & libc::POLLIN != 0 {
+                clear_resize();
+                return Err(resize_error());
+            }
+
+            if fds[0].revents == 0 {
+                continue;
+            }
+
+            let read = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+
+            if read < 0 {
+                let e = io::Error::last_os_error();
+
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+
+                return Err(e);
+            }
+
+            return Ok(read as usize);
+        }
+    }
+}
+
+// COLUMNS and LINES, for child processes that don't ask the terminal themselves
+pub fn update_size_variables(cfg: &mut config::Config) {
+    if let Ok((cols, lines)) = terminal_size() {
+        symbol_table::set_env_var("COLUMNS", cols.to_string().as_str(), cfg);
+        symbol_table::set_env_var("LINES", lines.to_string().as_str(), cfg);
+    }
+}
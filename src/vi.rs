@@ -0,0 +1,367 @@
+use crate::config;
+use crate::graphemes;
+use crate::tty;
+use crate::parser;
+use crate::{LineState, next_key, key_enter, key_edit_line, kill_text, push_kill_ring, key_up, key_down, move_vertically, key_clear_screen, key_search_history};
+use crate::{save_undo, record_undo, key_undo, key_redo, expand_abbreviation};
+
+use std::io::{self, Write};
+use termion::cursor;
+use termion::event::Key;
+use termion::raw::RawTerminal;
+
+// asks the terminal for whatever cursor shape the user had before
+pub const CURSOR_DEFAULT: &str = "\x1b[0 q";
+
+#[derive(PartialEq, Clone, Copy)]
+enum CharClass {
+    Blank,
+    Word,
+    Punctuation
+}
+
+fn char_class(ch: char) -> CharClass {
+    if ch.is_whitespace() {
+        CharClass::Blank
+    }
+
+    else if ch.is_alphanumeric() || ch == '_' {
+        CharClass::Word
+    }
+
+    else {
+        CharClass::Punctuation
+    }
+}
+
+// class of the grapheme starting at idx, going by its first character
+fn class_at(line: &str, idx: usize) -> Option<CharClass> {
+    line[idx..].chars().next().map(char_class)
+}
+
+fn class_before(line: &str, idx: usize) -> Option<CharClass> {
+    if idx == 0 {
+        return None;
+    }
+
+    class_at(line, graphemes::prev_boundary(line, idx))
+}
+
+// index of the last character, where the cursor stops in normal mode
+fn last_char(line: &str) -> usize {
+    graphemes::prev_boundary(line, line.len())
+}
+
+// w: start of the next word
+fn next_word_start(line: &str, idx: usize) -> usize {
+    let mut idx = idx;
+
+    let class = match class_at(line, idx) {
+        Some(class) => class,
+        None => return line.len()
+    };
+
+    if class != CharClass::Blank {
+        while class_at(line, idx) == Some(class) {
+            idx = graphemes::next_boundary(line, idx);
+        }
+    }
+
+    while class_at(line, idx) == Some(CharClass::Blank) {
+        idx = graphemes::next_boundary(line, idx);
+    }
+
+    idx
+}
+
+// b: start of the current word, or of the previous one when already there
+fn prev_word_start(line: &str, idx: usize) -> usize {
+    let mut idx = idx.min(line.len());
+
+    while class_before(line, idx) == Some(CharClass::Blank) {
+        idx = graphemes::prev_boundary(line, idx);
+    }
+
+    let class = match class_before(line, idx) {
+        Some(class) => class,
+        None => return 0
+    };
+
+    while class_before(line, idx) == Some(class) {
+        idx = graphemes::prev_boundary(line, idx);
+    }
+
+    idx
+}
+
+// e: last character of the current word, or of the next one when already there
+fn word_end(line: &str, idx: usize) -> usize {
+    let mut idx = graphemes::next_boundary(line, idx);
+
+    while class_at(line, idx) == Some(CharClass::Blank) {
+        idx = graphemes::next_boundary(line, idx);
+    }
+
+    let class = match class_at(line, idx) {
+        Some(class) => class,
+        None => return last_char(line)
+    };
+
+    loop {
+        let next = graphemes::next_boundary(line, idx);
+
+        if class_at(line, next) != Some(class) {
+            return idx;
+        }
+
+        idx = next;
+    }
+}
+
+// where a motion key takes the cursor, and whether the character it lands on is
+// included when an operator is applied over it
+fn motion(state: &LineState, key: Key) -> Option<(usize, bool)> {
+    let line = state.line.as_str();
+    let cur = state.insert_cur;
+
+    match key {
+        Key::Char('h') | Key::Left | Key::Backspace => Some((graphemes::prev_boundary(line, cur), false)),
+        Key::Char('l') | Key::Right | Key::Char(' ') => Some((graphemes::next_boundary(line, cur), false)),
+        Key::Char('w') => Some((next_word_start(line, cur), false)),
+        Key::Char('b') => Some((prev_word_start(line, cur), false)),
+        Key::Char('e') => Some((word_end(line, cur), true)),
+        Key::Char('0') | Key::Home => Some((0, false)),
+        Key::Char('^') => Some((line.find(|ch: char| !ch.is_ascii_whitespace()).unwrap_or(0), false)),
+        Key::Char('$') | Key::End => Some((last_char(line), true)),
+        _ => None
+    }
+}
+
+fn move_to(state: &mut LineState, idx: usize) {
+    state.insert_cur = idx.min(last_char(&state.line));
+}
+
+pub fn enter_normal_mode(stdout: &mut RawTerminal<io::Stdout>, state: &mut LineState) {
+    state.vi_normal = true;
+
+    // like vi, the cursor goes back onto the last character that was inserted
+    state.insert_cur = graphemes::prev_boundary(&state.line, state.insert_cur);
+
+    write!(stdout, "{}", cursor::SteadyBlock).unwrap();
+}
+
+// whatever gets typed until Esc is undone along with the change that started it
+fn enter_insert_mode(stdout: &mut RawTerminal<io::Stdout>, state: &mut LineState, idx: usize) {
+    state.vi_normal = false;
+    state.insert_cur = idx;
+    state.undo_group = true;
+
+    write!(stdout, "{}", cursor::SteadyBar).unwrap();
+}
+
+// d, c and y followed by a motion, or doubled to act on the whole line
+fn apply_operator(cfg: &mut config::Config, stdout: &mut RawTerminal<io::Stdout>, state: &mut LineState, op: char, key: Key) {
+    let cur = state.insert_cur;
+
+    let (start, end) = if key == Key::Char(op) {
+        (0, state.line.len())
+    }
+
+    else {
+        // cw only changes up to the end of the word, the same as ce
+        let key = if op == 'c' && key == Key::Char('w') { Key::Char('e') } else { key };
+
+        let (target, inclusive) = match motion(state, key) {
+            Some(target) => target,
+            None => return
+        };
+
+        if target < cur {
+            (target, cur)
+        }
+
+        else if inclusive {
+            (cur, graphemes::next_boundary(&state.line, target))
+        }
+
+        else {
+            (cur, target)
+        }
+    };
+
+    match op {
+        'y' => {
+            push_kill_ring(cfg, state.line[start..end].to_string());
+
+            if key != Key::Char(op) {
+                move_to(state, start);
+            }
+        },
+        'd' => {
+            save_undo(state);
+            kill_text(cfg, state, start, end);
+            move_to(state, start);
+        },
+        'c' => {
+            save_undo(state);
+            kill_text(cfg, state, start, end);
+            enter_insert_mode(stdout, state, start);
+        },
+        _ => unreachable!()
+    }
+}
+
+fn paste(cfg: &config::Config, state: &mut LineState, after: bool) {
+    let text = match cfg.kill_ring.last() {
+        Some(text) => text.clone(),
+        None => return
+    };
+
+    save_undo(state);
+
+    let idx = if after {
+        graphemes::next_boundary(&state.line, state.insert_cur)
+    }
+
+    else {
+        state.insert_cur
+    };
+
+    state.line.insert_str(idx, text.as_str());
+
+    let end = graphemes::prev_boundary(&state.line, idx + text.len());
+    move_to(state, end);
+}
+
+fn undo(state: &mut LineState, redo: bool) {
+    if redo {
+        key_redo(state);
+    }
+
+    else {
+        key_undo(state);
+    }
+
+    let idx = state.insert_cur;
+    move_to(state, idx);
+}
+
+// handles a key while in normal mode, returns whether the line was accepted
+pub fn normal_mode_key(cfg: &mut config::Config, stdout: &mut RawTerminal<io::Stdout>, events: &mut tty::Events, state: &mut LineState, key: Key) -> bool {
+    let cur = state.insert_cur;
+
+    if let Some((target, _inclusive)) = motion(state, key) {
+        move_to(state, target);
+        return false;
+    }
+
+    match key {
+        Key::Char('\n') => {
+            // the cursor sits on the last char in normal mode, an abbreviation at the
+            // end of the line is expanded the same as with accept-line
+            state.insert_cur = state.line.len();
+            expand_abbreviation(cfg, state);
+
+            // a continuation line is typed in insert mode
+            if parser::is_incomplete(&state.line) {
+                let len = state.line.len();
+                enter_insert_mode(stdout, state, len);
+            }
+
+            return key_enter(state);
+        },
+        Key::Ctrl('c') => {
+            state.interrupted = true;
+            return true;
+        },
+        Key::Ctrl('d') if state.line.is_empty() => {
+            state.eof = true;
+            return true;
+        },
+        Key::Ctrl('l') => key_clear_screen(cfg, stdout, state),
+        Key::Char(op) if op == 'd' || op == 'c' || op == 'y' => {
+            if let Some(motion_key) = next_key(events) {
+                apply_operator(cfg, stdout, state, op, motion_key);
+            }
+        },
+        Key::Char('D') => apply_operator(cfg, stdout, state, 'd', Key::Char('$')),
+        Key::Char('C') => apply_operator(cfg, stdout, state, 'c', Key::Char('$')),
+        Key::Char('x') | Key::Delete if !state.line.is_empty() => {
+            save_undo(state);
+            let end = graphemes::next_boundary(&state.line, cur);
+            kill_text(cfg, state, cur, end);
+            move_to(state, cur);
+        },
+        Key::Char('X') if cur > 0 => {
+            save_undo(state);
+            let start = graphemes::prev_boundary(&state.line, cur);
+            kill_text(cfg, state, start, cur);
+        },
+        Key::Char('p') => paste(cfg, state, true),
+        Key::Char('P') => paste(cfg, state, false),
+        Key::Char('u') => undo(state, false),
+        Key::Ctrl('r') => undo(state, true),
+        Key::Char('i') => {
+            save_undo(state);
+            enter_insert_mode(stdout, state, cur);
+        },
+        Key::Char('a') => {
+            save_undo(state);
+            let idx = graphemes::next_boundary(&state.line, cur);
+            enter_insert_mode(stdout, state, idx);
+        },
+        Key::Char('I') => {
+            save_undo(state);
+            enter_insert_mode(stdout, state, 0);
+        },
+        Key::Char('A') => {
+            save_undo(state);
+            let idx = state.line.len();
+            enter_insert_mode(stdout, state, idx);
+        },
+        Key::Char('k') | Key::Up => {
+            let before = (state.line.clone(), cur);
+
+            // between the lines of the buffer the column is kept, a recalled entry
+            // starts at its beginning
+            if move_vertically(state, true) {
+                let idx = state.insert_cur;
+                move_to(state, idx);
+            }
+
+            else {
+                key_up(cfg, state);
+                move_to(state, 0);
+            }
+
+            record_undo(state, before, false);
+        },
+        Key::Char('j') | Key::Down => {
+            let before = (state.line.clone(), cur);
+
+            if move_vertically(state, false) {
+                let idx = state.insert_cur;
+                move_to(state, idx);
+            }
+
+            else {
+                key_down(cfg, state);
+                move_to(state, 0);
+            }
+
+            record_undo(state, before, false);
+        },
+        Key::Char('v') => return key_edit_line(cfg, stdout, state, true),
+        Key::Char('/') | Key::Char('?') => {
+            if key_search_history(cfg, stdout, events, state, key == Key::Char('/')) {
+                return true;
+            }
+
+            let idx = state.insert_cur;
+            move_to(state, idx);
+        },
+        _ => {}
+    }
+
+    false
+}